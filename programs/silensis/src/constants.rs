@@ -1,15 +1,46 @@
+use anchor_lang::prelude::*;
+
 pub const PRICE_PRECISION: u64 = 1_000_000; // 6 decimals
+pub const PRICE_DECIMALS: u32 = 6;
 pub const SIZE_PRECISION: u64 = 1_000_000_000; // 9 decimals (lamports)
 pub const BPS_PRECISION: u64 = 10_000;
 pub const MAX_LEVERAGE: u64 = 50;
 pub const MAINTENANCE_MARGIN_BPS: u64 = 500; // 5%
 pub const LIQUIDATION_FEE_BPS: u64 = 50; // 0.5%
+pub const FULL_LIQUIDATION_MARGIN_BPS: u64 = 250; // 2.5%
+pub const LIQUIDATION_BUFFER_BPS: u64 = 100; // 1% above maintenance
+pub const MAX_TAKER_FEE_BPS: u64 = 100; // 1%
+pub const INSURANCE_FEE_SHARE_BPS: u64 = 2_000; // 20% of fees
+pub const LP_FEE_SHARE_BPS: u64 = 5_000; // 50% of fees left after the insurance share
 pub const MAX_ORACLE_STALENESS: i64 = 30; // seconds
+pub const MAX_ORACLE_CONFIDENCE_BPS: u64 = 200; // 2% of price
+pub const MAX_PRICE_PUBLISHERS: usize = 8;
+pub const PRICE_HISTORY_LEN: usize = 32;
+pub const PRICE_OBSERVATION_INTERVAL: i64 = 60; // seconds between stored observations
+pub const PRICE_EMA_PERIOD: i64 = 300; // seconds
+pub const DEFAULT_TWAP_WINDOW: i64 = 300; // seconds
+pub const MAX_TWAP_WINDOW: i64 = 1_800; // seconds, within the stored history
 pub const FUNDING_INTERVAL: i64 = 3600; // 1 hour
 pub const FUNDING_RATE_PRECISION: u128 = 1_000_000;
+pub const DEFAULT_SKEW_SCALE: u64 = 1_000_000_000_000; // $1M of net OI = 100% premium
+pub const DEFAULT_MAX_FUNDING_RATE_BPS: u64 = 100; // 1% per interval
+pub const DEFAULT_MAX_OPEN_INTEREST: u64 = 50_000_000_000_000; // $50M long + short
+pub const DEFAULT_MAX_SIDE_OPEN_INTEREST: u64 = 25_000_000_000_000; // $25M per side
+pub const DEFAULT_MAX_POSITION_NOTIONAL: u64 = 5_000_000_000_000; // $5M
+pub const DEFAULT_MAX_USER_NOTIONAL: u64 = 10_000_000_000_000; // $10M across all markets
+pub const ORDER_EXECUTION_FEE: u64 = 100_000; // $0.10 to the keeper that fills an order
+pub const ORDER_SHORT_FILL_BAND_BPS: u64 = 500; // short orders fill at most 5% above their limit
+pub const TRIGGER_EXECUTION_FEE: u64 = 100_000; // $0.10 to the keeper that fires a trigger
 
 pub const GLOBAL_STATE_SEED: &[u8] = b"global_state";
 pub const USER_VAULT_SEED: &[u8] = b"user_vault";
 pub const POSITION_SEED: &[u8] = b"position";
+pub const ORDER_SEED: &[u8] = b"order";
 pub const TREASURY_SEED: &[u8] = b"treasury";
 pub const PRICE_FEED_SEED: &[u8] = b"price_feed";
+pub const MARKET_SEED: &[u8] = b"market";
+pub const INSURANCE_FUND_SEED: &[u8] = b"insurance_fund";
+pub const LIQUIDITY_POOL_SEED: &[u8] = b"liquidity_pool";
+pub const LP_MINT_SEED: &[u8] = b"lp_mint";
+
+pub const PYTH_PROGRAM_ID: Pubkey = pubkey!("FsJ3A3u2vn5cTVofAjvy6y5kwABJAqYWpe4975bi2epH");
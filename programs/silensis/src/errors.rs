@@ -24,6 +24,8 @@ pub enum PerpsError {
     InvalidParameter,
     #[msg("Unauthorized access")]
     Unauthorized,
+    // No longer returned: funding accrues every second. Kept so the codes
+    // of later variants stay stable for clients.
     #[msg("Funding interval not elapsed")]
     FundingIntervalNotElapsed,
     #[msg("Invalid leverage value")]
@@ -32,4 +34,52 @@ pub enum PerpsError {
     ZeroSize,
     #[msg("Withdrawal amount must be greater than zero")]
     ZeroAmount,
+    #[msg("Operation not allowed in the vault's margin mode")]
+    MarginModeMismatch,
+    #[msg("Margin mode cannot change while positions are open")]
+    OpenPositionsExist,
+    #[msg("Remaining accounts must list every open position with its market and price feed")]
+    InvalidRemainingAccounts,
+    #[msg("Account is above maintenance margin")]
+    AccountNotLiquidatable,
+    #[msg("Oracle account does not match the market's oracle configuration")]
+    InvalidOracle,
+    #[msg("Oracle confidence interval is too wide")]
+    OracleConfidenceTooWide,
+    #[msg("Not enough fresh oracle submissions")]
+    OracleQuorumNotMet,
+    #[msg("Market open interest cap exceeded")]
+    OpenInterestCapExceeded,
+    #[msg("Open interest cap for this side exceeded")]
+    SideOpenInterestCapExceeded,
+    #[msg("Position notional exceeds the market's maximum")]
+    PositionNotionalCapExceeded,
+    #[msg("Open notional exceeds the per-user maximum")]
+    UserNotionalCapExceeded,
+    #[msg("Trade exceeds the vAMM's liquidity")]
+    InsufficientLiquidity,
+    #[msg("Operation not allowed in the market's pricing mode")]
+    PricingModeMismatch,
+    #[msg("Market pricing cannot change while it has open interest")]
+    MarketHasOpenInterest,
+    #[msg("Liquidity pool balance cannot cover the payout")]
+    InsufficientPoolLiquidity,
+    #[msg("Order expiry must be in the future")]
+    InvalidExpiry,
+    #[msg("Order has expired")]
+    OrderExpired,
+    #[msg("Price has not crossed the order's limit")]
+    OrderNotTriggered,
+    #[msg("Price has not crossed any of the position's triggers")]
+    TriggerNotReached,
+    #[msg("Fill price is worse than the acceptable price")]
+    SlippageExceeded,
+    #[msg("Transaction landed after its expiry timestamp")]
+    TransactionExpired,
+    #[msg("Pyth oracles provide no TWAP risk price")]
+    TwapUnavailableForPyth,
+    #[msg("Liquidity pool shares have no value backing them")]
+    PoolInsolvent,
+    #[msg("Order has not expired yet")]
+    OrderNotExpired,
 }
@@ -0,0 +1,105 @@
+use anchor_lang::prelude::*;
+use crate::state::Direction;
+
+/// Emitted when a settlement loss exceeds what the position could cover.
+#[event]
+pub struct ShortfallEvent {
+    pub market_index: u16,
+    pub position_id: u64,
+    pub owner: Pubkey,
+    pub shortfall: u64,
+    pub covered_by_insurance: u64,
+    pub bad_debt: u64,
+    pub timestamp: i64,
+}
+
+/// Emitted when a cross-margin account is liquidated as a whole.
+#[event]
+pub struct AccountLiquidatedEvent {
+    pub owner: Pubkey,
+    pub positions_closed: u32,
+    pub equity: i64,
+    pub maintenance_margin: u64,
+    pub liquidation_fee: u64,
+    pub shortfall: u64,
+    pub covered_by_insurance: u64,
+    pub bad_debt: u64,
+    pub timestamp: i64,
+}
+
+/// Emitted when a limit order is placed.
+#[event]
+pub struct OrderPlacedEvent {
+    pub owner: Pubkey,
+    pub market_index: u16,
+    pub order_id: u64,
+    pub direction: Direction,
+    pub size: u64,
+    pub limit_price: u64,
+    pub leverage: u64,
+    pub expiry: i64,
+    pub timestamp: i64,
+}
+
+/// Emitted when a keeper fills a limit order into a position.
+#[event]
+pub struct OrderExecutedEvent {
+    pub owner: Pubkey,
+    pub market_index: u16,
+    pub order_id: u64,
+    pub position_id: u64,
+    pub keeper: Pubkey,
+    pub fill_price: u64,
+    pub execution_fee: u64,
+    pub timestamp: i64,
+}
+
+/// Emitted when a keeper closes a position on a stop-loss, take-profit or
+/// trailing stop.
+#[event]
+pub struct TriggerExecutedEvent {
+    pub owner: Pubkey,
+    pub market_index: u16,
+    pub position_id: u64,
+    pub executor: Pubkey,
+    pub trigger_price: u64, // oracle price that crossed the trigger
+    pub fill_price: u64,
+    pub pnl: i64,
+    pub execution_fee: u64,
+    pub timestamp: i64,
+}
+
+/// Emitted when USDC is added to or removed from the liquidity pool.
+#[event]
+pub struct LiquidityChangedEvent {
+    pub provider: Pubkey,
+    pub amount: i64,       // positive when added
+    pub shares: i64,       // positive when minted
+    pub nav: u64,          // before the change
+    pub share_supply: u64, // before the change
+    pub timestamp: i64,
+}
+
+/// Emitted when a vAMM market's peg is moved toward the oracle price.
+#[event]
+pub struct VammRepeggedEvent {
+    pub market_index: u16,
+    pub oracle_price: u64,
+    pub old_price: u64,
+    pub new_price: u64,
+    pub peg_multiplier: u128,
+    pub cost: i64,
+    pub timestamp: i64,
+}
+
+/// Emitted when a market's funding rate is applied.
+#[event]
+pub struct FundingAppliedEvent {
+    pub market_index: u16,
+    pub index_price: u64,
+    pub mark_price: u64,
+    pub funding_rate: i64,
+    pub long_oi: u64,
+    pub short_oi: u64,
+    pub timestamp: i64,
+}
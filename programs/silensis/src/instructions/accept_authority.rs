@@ -0,0 +1,27 @@
+use anchor_lang::prelude::*;
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::state::GlobalState;
+
+pub fn handle_accept_authority(ctx: Context<AcceptAuthority>) -> Result<()> {
+    let global = &mut ctx.accounts.global_state;
+    global.authority = ctx.accounts.pending_authority.key();
+    global.pending_authority = Pubkey::default();
+
+    msg!("Authority transferred to {}", global.authority);
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct AcceptAuthority<'info> {
+    pub pending_authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATE_SEED],
+        bump = global_state.bump,
+        has_one = pending_authority @ PerpsError::Unauthorized,
+    )]
+    pub global_state: Account<'info, GlobalState>,
+}
@@ -0,0 +1,128 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token::{self, Mint, MintTo, Token, TokenAccount, Transfer};
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::events::LiquidityChangedEvent;
+use crate::pool::load_pool_nav;
+use crate::state::{GlobalState, LiquidityPool};
+
+/// Deposit USDC into the liquidity pool for share tokens priced at NAV.
+/// Remaining accounts: a (market, price feed) pair for every listed market,
+/// in market index order, so unrealized trader PnL is reflected in NAV.
+pub fn handle_add_liquidity<'info>(
+    ctx: Context<'_, '_, 'info, 'info, AddLiquidity<'info>>,
+    amount: u64,
+) -> Result<()> {
+    require!(amount > 0, PerpsError::ZeroAmount);
+
+    let clock = Clock::get()?;
+    let nav = load_pool_nav(
+        &ctx.accounts.liquidity_pool,
+        ctx.accounts.global_state.market_count,
+        ctx.remaining_accounts,
+        clock.unix_timestamp,
+    )?;
+    let share_supply = ctx.accounts.lp_mint.supply;
+    let shares = LiquidityPool::shares_for_deposit(amount, nav, share_supply)?;
+    require!(shares > 0, PerpsError::ZeroAmount);
+
+    // Transfer USDC from provider to treasury
+    let cpi_accounts = Transfer {
+        from: ctx.accounts.provider_ata.to_account_info(),
+        to: ctx.accounts.treasury.to_account_info(),
+        authority: ctx.accounts.provider.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+    token::transfer(cpi_ctx, amount)?;
+
+    // Mint shares to the provider (pool PDA signer)
+    let seeds = &[LIQUIDITY_POOL_SEED, &[ctx.accounts.liquidity_pool.bump]];
+    let signer_seeds = &[&seeds[..]];
+
+    let cpi_accounts = MintTo {
+        mint: ctx.accounts.lp_mint.to_account_info(),
+        to: ctx.accounts.provider_share_ata.to_account_info(),
+        authority: ctx.accounts.liquidity_pool.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(
+        ctx.accounts.token_program.to_account_info(),
+        cpi_accounts,
+        signer_seeds,
+    );
+    token::mint_to(cpi_ctx, shares)?;
+
+    // Income the pool earned before it had any LPs belongs to the protocol,
+    // not to the first depositor
+    let liquidity_pool = &mut ctx.accounts.liquidity_pool;
+    if share_supply == 0 {
+        let global = &mut ctx.accounts.global_state;
+        global.protocol_fee_balance = global
+            .protocol_fee_balance
+            .checked_add(liquidity_pool.balance)
+            .ok_or(PerpsError::MathOverflow)?;
+        liquidity_pool.balance = 0;
+    }
+    liquidity_pool.balance = liquidity_pool
+        .balance
+        .checked_add(amount)
+        .ok_or(PerpsError::MathOverflow)?;
+
+    emit!(LiquidityChangedEvent {
+        provider: ctx.accounts.provider.key(),
+        amount: amount as i64,
+        shares: shares as i64,
+        nav,
+        share_supply,
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct AddLiquidity<'info> {
+    pub provider: Signer<'info>,
+
+    #[account(
+        mut,
+        token::mint = global_state.usdc_mint,
+        token::authority = provider,
+    )]
+    pub provider_ata: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        token::mint = lp_mint,
+        token::authority = provider,
+    )]
+    pub provider_share_ata: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATE_SEED],
+        bump = global_state.bump,
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(
+        mut,
+        seeds = [LIQUIDITY_POOL_SEED],
+        bump = liquidity_pool.bump,
+        has_one = lp_mint,
+    )]
+    pub liquidity_pool: Account<'info, LiquidityPool>,
+
+    #[account(mut)]
+    pub lp_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED],
+        bump,
+        token::mint = global_state.usdc_mint,
+        token::authority = global_state,
+    )]
+    pub treasury: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+}
@@ -0,0 +1,57 @@
+use anchor_lang::prelude::*;
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::math::calculate_notional;
+use crate::state::{Position, UserVault};
+
+pub fn handle_add_margin(ctx: Context<AddMargin>, amount: u64) -> Result<()> {
+    require!(amount > 0, PerpsError::ZeroAmount);
+
+    let available = ctx.accounts.user_vault.free_balance()?;
+    require!(amount <= available, PerpsError::InsufficientBalance);
+
+    // Lock the added margin in vault
+    let vault = &mut ctx.accounts.user_vault;
+    vault.locked_margin = vault
+        .locked_margin
+        .checked_add(amount)
+        .ok_or(PerpsError::MathOverflow)?;
+
+    let position = &mut ctx.accounts.position;
+    position.margin = position
+        .margin
+        .checked_add(amount)
+        .ok_or(PerpsError::MathOverflow)?;
+    position.leverage = calculate_notional(position.size, position.entry_price)?
+        .checked_div(position.margin)
+        .ok_or(PerpsError::MathOverflow)?;
+
+    msg!(
+        "Added {} margin to position {}. Margin: {}",
+        amount,
+        position.position_id,
+        position.margin
+    );
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct AddMargin<'info> {
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [USER_VAULT_SEED, user.key().as_ref()],
+        bump = user_vault.bump,
+        constraint = user_vault.owner == user.key() @ PerpsError::Unauthorized,
+    )]
+    pub user_vault: Account<'info, UserVault>,
+
+    #[account(
+        mut,
+        constraint = position.owner == user.key() @ PerpsError::Unauthorized,
+        constraint = position.is_open @ PerpsError::PositionNotOpen,
+    )]
+    pub position: Account<'info, Position>,
+}
@@ -1,52 +1,41 @@
 use anchor_lang::prelude::*;
 use crate::constants::*;
 use crate::errors::PerpsError;
-use crate::math::calculate_funding_rate;
-use crate::state::{GlobalState, PriceFeed};
+use crate::events::FundingAppliedEvent;
+use crate::oracle::read_risk_price;
+use crate::state::Market;
 
+/// Accrue a market's funding up to now. Funding also accrues on every trade,
+/// liquidation and parameter update; this lets anyone checkpoint an idle market.
 pub fn handle_apply_funding(ctx: Context<ApplyFunding>) -> Result<()> {
     let clock = Clock::get()?;
-    let global = &ctx.accounts.global_state;
 
-    let time_elapsed = clock
-        .unix_timestamp
-        .checked_sub(global.last_funding_time)
-        .ok_or(PerpsError::MathOverflow)?;
+    // Index and mark price are reported with the event; the funding rate
+    // itself is the OI skew premium and does not depend on them
+    let market = &ctx.accounts.market;
+    let index_price = read_risk_price(market, &ctx.accounts.price_feed, clock.unix_timestamp)?;
+    let mark_price = market.mark_price(index_price)?;
 
-    require!(
-        time_elapsed >= FUNDING_INTERVAL,
-        PerpsError::FundingIntervalNotElapsed
-    );
-
-    // Get current price from oracle
-    let price_feed = &ctx.accounts.price_feed;
-    require!(
-        clock.unix_timestamp - price_feed.timestamp <= MAX_ORACLE_STALENESS,
-        PerpsError::OracleStale
-    );
-    require!(price_feed.price > 0, PerpsError::OracleInvalidPrice);
-
-    // Calculate funding rate based on OI imbalance
-    let funding_rate = calculate_funding_rate(global.total_long_oi, global.total_short_oi)?;
-
-    // Update cumulative funding rates
-    let global = &mut ctx.accounts.global_state;
-    global.cumulative_funding_rate_long = global
-        .cumulative_funding_rate_long
-        .checked_add(funding_rate as i128)
-        .ok_or(PerpsError::MathOverflow)?;
-    global.cumulative_funding_rate_short = global
-        .cumulative_funding_rate_short
-        .checked_sub(funding_rate as i128)
-        .ok_or(PerpsError::MathOverflow)?;
+    // Accrue the elapsed seconds at the premium rate, clamped by the market's cap
+    let market = &mut ctx.accounts.market;
+    let funding_rate = market.accrue_funding(clock.unix_timestamp)?;
 
-    global.last_funding_time = clock.unix_timestamp;
+    emit!(FundingAppliedEvent {
+        market_index: market.market_index,
+        index_price,
+        mark_price,
+        funding_rate,
+        long_oi: market.total_long_oi,
+        short_oi: market.total_short_oi,
+        timestamp: clock.unix_timestamp,
+    });
 
     msg!(
-        "Funding applied. Rate: {}, Long OI: {}, Short OI: {}",
-        funding_rate,
-        global.total_long_oi,
-        global.total_short_oi
+        "Funding applied to market {}. Index: {}, Mark: {}, Rate: {}",
+        market.market_index,
+        index_price,
+        mark_price,
+        funding_rate
     );
 
     Ok(())
@@ -59,14 +48,12 @@ pub struct ApplyFunding<'info> {
 
     #[account(
         mut,
-        seeds = [GLOBAL_STATE_SEED],
-        bump = global_state.bump,
+        seeds = [MARKET_SEED, market.market_index.to_le_bytes().as_ref()],
+        bump = market.bump,
+        has_one = price_feed @ PerpsError::InvalidOracle,
     )]
-    pub global_state: Account<'info, GlobalState>,
+    pub market: Account<'info, Market>,
 
-    #[account(
-        seeds = [PRICE_FEED_SEED],
-        bump = price_feed.bump,
-    )]
-    pub price_feed: Account<'info, PriceFeed>,
+    /// CHECK: must be the market's configured oracle; parsed by `read_oracle_price`
+    pub price_feed: UncheckedAccount<'info>,
 }
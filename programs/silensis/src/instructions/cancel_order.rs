@@ -0,0 +1,40 @@
+use anchor_lang::prelude::*;
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::state::{Order, UserVault};
+
+pub fn handle_cancel_order(ctx: Context<CancelOrder>) -> Result<()> {
+    let order = &ctx.accounts.order;
+
+    // Release the escrow; the order account's rent goes back to the owner
+    let vault = &mut ctx.accounts.user_vault;
+    vault.locked_margin = vault
+        .locked_margin
+        .checked_sub(order.escrow)
+        .ok_or(PerpsError::MathOverflow)?;
+
+    msg!("Order {} cancelled", order.order_id);
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct CancelOrder<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [USER_VAULT_SEED, user.key().as_ref()],
+        bump = user_vault.bump,
+        constraint = user_vault.owner == user.key() @ PerpsError::Unauthorized,
+    )]
+    pub user_vault: Account<'info, UserVault>,
+
+    #[account(
+        mut,
+        close = user,
+        constraint = order.owner == user.key() @ PerpsError::Unauthorized,
+    )]
+    pub order: Account<'info, Order>,
+}
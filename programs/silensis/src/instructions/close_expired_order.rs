@@ -0,0 +1,45 @@
+use anchor_lang::prelude::*;
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::state::{Order, UserVault};
+
+/// Close a limit order that can no longer fill. Callable by anyone: the
+/// escrow is released and the order account's lamports go to its owner.
+pub fn handle_close_expired_order(ctx: Context<CloseExpiredOrder>) -> Result<()> {
+    let order = &ctx.accounts.order;
+    require!(
+        Clock::get()?.unix_timestamp > order.expiry,
+        PerpsError::OrderNotExpired
+    );
+
+    let vault = &mut ctx.accounts.owner_vault;
+    vault.locked_margin = vault
+        .locked_margin
+        .checked_sub(order.escrow)
+        .ok_or(PerpsError::MathOverflow)?;
+
+    msg!("Expired order {} closed", order.order_id);
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct CloseExpiredOrder<'info> {
+    pub caller: Signer<'info>,
+
+    #[account(mut, address = order.owner @ PerpsError::Unauthorized)]
+    pub owner: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [USER_VAULT_SEED, order.owner.as_ref()],
+        bump = owner_vault.bump,
+    )]
+    pub owner_vault: Account<'info, UserVault>,
+
+    #[account(
+        mut,
+        close = owner,
+    )]
+    pub order: Account<'info, Order>,
+}
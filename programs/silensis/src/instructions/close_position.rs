@@ -1,88 +1,61 @@
 use anchor_lang::prelude::*;
 use crate::constants::*;
 use crate::errors::PerpsError;
-use crate::math::calculate_pnl;
-use crate::state::{Direction, GlobalState, PriceFeed, Position, UserVault};
-
-pub fn handle_close_position(ctx: Context<ClosePosition>) -> Result<()> {
+use crate::oracle::read_oracle_price;
+use crate::settlement::{settle_close, CloseTrade};
+use crate::state::{GlobalState, InsuranceFund, LiquidityPool, Market, Position, UserVault};
+
+pub fn handle_close_position(
+    ctx: Context<ClosePosition>,
+    acceptable_price: Option<u64>, // min fill price for longs, max for shorts
+    expiry_timestamp: Option<i64>,
+) -> Result<()> {
     let position = &ctx.accounts.position;
     require!(position.is_open, PerpsError::PositionNotOpen);
 
-    // Get current price from oracle
-    let price_feed = &ctx.accounts.price_feed;
     let clock = Clock::get()?;
-    require!(
-        clock.unix_timestamp - price_feed.timestamp <= MAX_ORACLE_STALENESS,
-        PerpsError::OracleStale
-    );
-    require!(price_feed.price > 0, PerpsError::OracleInvalidPrice);
-    let current_price = price_feed.price;
+    if let Some(expiry_timestamp) = expiry_timestamp {
+        require!(
+            clock.unix_timestamp <= expiry_timestamp,
+            PerpsError::TransactionExpired
+        );
+    }
 
-    // Calculate PnL
-    let pnl = calculate_pnl(
-        position.direction,
-        position.size,
-        position.entry_price,
-        current_price,
+    // Get current price from oracle
+    let current_price = read_oracle_price(
+        &ctx.accounts.market,
+        &ctx.accounts.price_feed,
+        clock.unix_timestamp,
     )?;
 
-    let margin = position.margin;
-
-    // Calculate notional for OI update
-    let notional = (position.size as u128)
-        .checked_mul(position.entry_price as u128)
-        .ok_or(PerpsError::MathOverflow)?
-        .checked_div(SIZE_PRECISION as u128)
-        .ok_or(PerpsError::MathOverflow)? as u64;
-
-    // Settle: new_balance = margin + pnl (clamped to 0 minimum)
-    let settlement = if pnl >= 0 {
-        margin
-            .checked_add(pnl as u64)
-            .ok_or(PerpsError::MathOverflow)?
-    } else {
-        let loss = (-pnl) as u64;
-        margin.saturating_sub(loss)
+    let trade = CloseTrade {
+        size: ctx.accounts.position.size,
+        oracle_price: current_price,
     };
-
-    // Update vault: unlock margin and adjust balance
-    let vault = &mut ctx.accounts.user_vault;
-    vault.locked_margin = vault
-        .locked_margin
-        .checked_sub(margin)
-        .ok_or(PerpsError::MathOverflow)?;
-
-    // Adjust deposited_amount by PnL
-    if pnl >= 0 {
-        vault.deposited_amount = vault
-            .deposited_amount
-            .checked_add(pnl as u64)
-            .ok_or(PerpsError::MathOverflow)?;
-    } else {
-        let loss = (-pnl) as u64;
-        vault.deposited_amount = vault.deposited_amount.saturating_sub(loss);
-    }
-
-    // Update global state open interest
-    let global = &mut ctx.accounts.global_state;
-    match position.direction {
-        Direction::Long => {
-            global.total_long_oi = global.total_long_oi.saturating_sub(notional);
-        }
-        Direction::Short => {
-            global.total_short_oi = global.total_short_oi.saturating_sub(notional);
-        }
+    let closed = settle_close(
+        &mut ctx.accounts.position,
+        &mut ctx.accounts.user_vault,
+        &mut ctx.accounts.global_state,
+        &mut ctx.accounts.market,
+        &mut ctx.accounts.insurance_fund,
+        &mut ctx.accounts.liquidity_pool,
+        trade,
+    )?;
+    if let Some(acceptable_price) = acceptable_price {
+        let exit_direction = ctx.accounts.position.direction.opposite();
+        require!(
+            exit_direction.is_price_acceptable(closed.fill_price, acceptable_price),
+            PerpsError::SlippageExceeded
+        );
     }
 
-    // Mark position as closed
-    let position = &mut ctx.accounts.position;
-    position.is_open = false;
-
     msg!(
-        "Position {} closed. PnL: {}, Settlement: {}",
-        position.position_id,
-        pnl,
-        settlement
+        "Position {} closed. PnL: {}, Funding: {}, Fee: {}, Settlement: {}",
+        ctx.accounts.position.position_id,
+        closed.pnl,
+        closed.funding_payment,
+        closed.fee,
+        closed.settlement
     );
 
     Ok(())
@@ -116,8 +89,27 @@ pub struct ClosePosition<'info> {
     pub global_state: Account<'info, GlobalState>,
 
     #[account(
-        seeds = [PRICE_FEED_SEED],
-        bump = price_feed.bump,
+        mut,
+        seeds = [MARKET_SEED, position.market_index.to_le_bytes().as_ref()],
+        bump = market.bump,
+        has_one = price_feed @ PerpsError::InvalidOracle,
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [INSURANCE_FUND_SEED],
+        bump = insurance_fund.bump,
     )]
-    pub price_feed: Account<'info, PriceFeed>,
+    pub insurance_fund: Account<'info, InsuranceFund>,
+
+    #[account(
+        mut,
+        seeds = [LIQUIDITY_POOL_SEED],
+        bump = liquidity_pool.bump,
+    )]
+    pub liquidity_pool: Account<'info, LiquidityPool>,
+
+    /// CHECK: must be the market's configured oracle; parsed by `read_oracle_price`
+    pub price_feed: UncheckedAccount<'info>,
 }
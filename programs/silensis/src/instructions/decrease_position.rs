@@ -0,0 +1,110 @@
+use anchor_lang::prelude::*;
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::oracle::read_oracle_price;
+use crate::settlement::{settle_close, CloseTrade};
+use crate::state::{GlobalState, InsuranceFund, LiquidityPool, Market, Position, UserVault};
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct DecreasePositionParams {
+    pub size: u64,
+}
+
+pub fn handle_decrease_position(
+    ctx: Context<DecreasePosition>,
+    params: DecreasePositionParams,
+) -> Result<()> {
+    let position = &ctx.accounts.position;
+    require!(position.is_open, PerpsError::PositionNotOpen);
+    require!(params.size > 0, PerpsError::ZeroSize);
+    // Closing the full size goes through close_position
+    require!(params.size < position.size, PerpsError::InvalidParameter);
+
+    // Get current price from oracle
+    let clock = Clock::get()?;
+    let current_price = read_oracle_price(
+        &ctx.accounts.market,
+        &ctx.accounts.price_feed,
+        clock.unix_timestamp,
+    )?;
+
+    // Realize PnL and funding on the closed slice only; entry price and
+    // funding snapshot carry over to the rest
+    let closed = settle_close(
+        &mut ctx.accounts.position,
+        &mut ctx.accounts.user_vault,
+        &mut ctx.accounts.global_state,
+        &mut ctx.accounts.market,
+        &mut ctx.accounts.insurance_fund,
+        &mut ctx.accounts.liquidity_pool,
+        CloseTrade {
+            size: params.size,
+            oracle_price: current_price,
+        },
+    )?;
+
+    msg!(
+        "Position {} decreased by {}. PnL: {}, Funding: {}, Fee: {}, Settlement: {}",
+        ctx.accounts.position.position_id,
+        params.size,
+        closed.pnl,
+        closed.funding_payment,
+        closed.fee,
+        closed.settlement
+    );
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct DecreasePosition<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [USER_VAULT_SEED, user.key().as_ref()],
+        bump = user_vault.bump,
+        constraint = user_vault.owner == user.key() @ PerpsError::Unauthorized,
+    )]
+    pub user_vault: Account<'info, UserVault>,
+
+    #[account(
+        mut,
+        constraint = position.owner == user.key() @ PerpsError::Unauthorized,
+        constraint = position.is_open @ PerpsError::PositionNotOpen,
+    )]
+    pub position: Account<'info, Position>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATE_SEED],
+        bump = global_state.bump,
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(
+        mut,
+        seeds = [MARKET_SEED, position.market_index.to_le_bytes().as_ref()],
+        bump = market.bump,
+        has_one = price_feed @ PerpsError::InvalidOracle,
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [INSURANCE_FUND_SEED],
+        bump = insurance_fund.bump,
+    )]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+
+    #[account(
+        mut,
+        seeds = [LIQUIDITY_POOL_SEED],
+        bump = liquidity_pool.bump,
+    )]
+    pub liquidity_pool: Account<'info, LiquidityPool>,
+
+    /// CHECK: must be the market's configured oracle; parsed by `read_oracle_price`
+    pub price_feed: UncheckedAccount<'info>,
+}
@@ -0,0 +1,163 @@
+use anchor_lang::prelude::*;
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::events::OrderExecutedEvent;
+use crate::margin::cross_margin_headroom;
+use crate::oracle::read_oracle_price;
+use crate::settlement::{settle_open, OpenTrade};
+use crate::state::{GlobalState, InsuranceFund, LiquidityPool, Market, Order, Position, UserVault};
+
+/// Fill a limit order into a position once the oracle price crosses its limit.
+/// Callable by anyone; the keeper is paid the order's execution fee.
+/// Remaining accounts, for a cross-margin owner: a (position, market, price
+/// feed) triple for each of the owner's open positions.
+pub fn handle_execute_order<'info>(
+    ctx: Context<'_, '_, 'info, 'info, ExecuteOrder<'info>>,
+) -> Result<()> {
+    let global = &ctx.accounts.global_state;
+    require!(!global.is_paused, PerpsError::ProtocolPaused);
+
+    let order = &ctx.accounts.order;
+    let clock = Clock::get()?;
+    require!(clock.unix_timestamp <= order.expiry, PerpsError::OrderExpired);
+
+    let current_price = read_oracle_price(
+        &ctx.accounts.market,
+        &ctx.accounts.price_feed,
+        clock.unix_timestamp,
+    )?;
+    require!(order.is_triggered(current_price)?, PerpsError::OrderNotTriggered);
+
+    // Release the escrow and pay the keeper out of it
+    let vault = &mut ctx.accounts.owner_vault;
+    vault.locked_margin = vault
+        .locked_margin
+        .checked_sub(order.escrow)
+        .ok_or(PerpsError::MathOverflow)?;
+    vault.deposited_amount = vault
+        .deposited_amount
+        .checked_sub(order.execution_fee)
+        .ok_or(PerpsError::MathOverflow)?;
+
+    ctx.accounts.keeper_vault.credit(
+        ctx.accounts.keeper.key(),
+        ctx.bumps.keeper_vault,
+        order.execution_fee,
+    )?;
+
+    // Open as a market order would, with the released escrow in the free
+    // balance, but never fill worse than the limit
+    let cross_headroom = cross_margin_headroom(
+        &ctx.accounts.owner_vault,
+        ctx.remaining_accounts,
+        clock.unix_timestamp,
+        read_oracle_price,
+    )?;
+    let opened = settle_open(
+        &mut ctx.accounts.position,
+        &mut ctx.accounts.owner_vault,
+        &mut ctx.accounts.global_state,
+        &mut ctx.accounts.market,
+        &mut ctx.accounts.insurance_fund,
+        &mut ctx.accounts.liquidity_pool,
+        OpenTrade {
+            owner: order.owner,
+            direction: order.direction,
+            size: order.size,
+            leverage: order.leverage,
+            oracle_price: current_price,
+            cross_headroom,
+        },
+    )?;
+    ctx.accounts.position.bump = ctx.bumps.position;
+    require!(order.is_triggered(opened.fill_price)?, PerpsError::OrderNotTriggered);
+
+    emit!(OrderExecutedEvent {
+        owner: order.owner,
+        market_index: order.market_index,
+        order_id: order.order_id,
+        position_id: ctx.accounts.position.position_id,
+        keeper: ctx.accounts.keeper.key(),
+        fill_price: opened.fill_price,
+        execution_fee: order.execution_fee,
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct ExecuteOrder<'info> {
+    #[account(
+        mut,
+        constraint = keeper.key() != order.owner @ PerpsError::Unauthorized,
+    )]
+    pub keeper: Signer<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = keeper,
+        space = UserVault::LEN,
+        seeds = [USER_VAULT_SEED, keeper.key().as_ref()],
+        bump,
+    )]
+    pub keeper_vault: Account<'info, UserVault>,
+
+    /// Closed to the keeper: its lamports, including the position rent
+    /// prefunded at placement, cover the position the keeper creates
+    #[account(
+        mut,
+        close = keeper,
+    )]
+    pub order: Account<'info, Order>,
+
+    #[account(
+        mut,
+        seeds = [USER_VAULT_SEED, order.owner.as_ref()],
+        bump = owner_vault.bump,
+    )]
+    pub owner_vault: Account<'info, UserVault>,
+
+    #[account(
+        init,
+        payer = keeper,
+        space = Position::LEN,
+        seeds = [POSITION_SEED, order.owner.as_ref(), global_state.next_position_id.to_le_bytes().as_ref()],
+        bump,
+    )]
+    pub position: Account<'info, Position>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATE_SEED],
+        bump = global_state.bump,
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(
+        mut,
+        seeds = [MARKET_SEED, order.market_index.to_le_bytes().as_ref()],
+        bump = market.bump,
+        has_one = price_feed @ PerpsError::InvalidOracle,
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [INSURANCE_FUND_SEED],
+        bump = insurance_fund.bump,
+    )]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+
+    #[account(
+        mut,
+        seeds = [LIQUIDITY_POOL_SEED],
+        bump = liquidity_pool.bump,
+    )]
+    pub liquidity_pool: Account<'info, LiquidityPool>,
+
+    /// CHECK: must be the market's configured oracle; parsed by `read_oracle_price`
+    pub price_feed: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
@@ -0,0 +1,138 @@
+use anchor_lang::prelude::*;
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::events::TriggerExecutedEvent;
+use crate::oracle::read_oracle_price;
+use crate::settlement::{settle_close, CloseTrade};
+use crate::state::{GlobalState, InsuranceFund, LiquidityPool, Market, Position, UserVault};
+
+/// Close a position whose stop-loss, take-profit or trailing stop the oracle
+/// price has crossed, settling as `close_position` does. Callable by anyone;
+/// the executor is paid a fee from the owner's balance.
+/// While nothing is crossed, a new high (long) or low (short) is recorded as
+/// the trailing stop's reference instead.
+pub fn handle_execute_trigger(ctx: Context<ExecuteTrigger>) -> Result<()> {
+    let clock = Clock::get()?;
+    let current_price = read_oracle_price(
+        &ctx.accounts.market,
+        &ctx.accounts.price_feed,
+        clock.unix_timestamp,
+    )?;
+
+    let position = &mut ctx.accounts.position;
+    if !position.is_trigger_crossed(current_price)? {
+        require!(
+            position.update_trailing_price(current_price),
+            PerpsError::TriggerNotReached
+        );
+
+        msg!(
+            "Position {} trailing stop moved to {}",
+            position.position_id,
+            position.trailing_price
+        );
+        return Ok(());
+    }
+
+    let trade = CloseTrade {
+        size: ctx.accounts.position.size,
+        oracle_price: current_price,
+    };
+    let closed = settle_close(
+        &mut ctx.accounts.position,
+        &mut ctx.accounts.owner_vault,
+        &mut ctx.accounts.global_state,
+        &mut ctx.accounts.market,
+        &mut ctx.accounts.insurance_fund,
+        &mut ctx.accounts.liquidity_pool,
+        trade,
+    )?;
+
+    // Pay the executor from what the owner has left
+    let execution_fee = ctx.accounts.owner_vault.charge_fee(TRIGGER_EXECUTION_FEE)?;
+    ctx.accounts.executor_vault.credit(
+        ctx.accounts.executor.key(),
+        ctx.bumps.executor_vault,
+        execution_fee,
+    )?;
+
+    let position = &ctx.accounts.position;
+    emit!(TriggerExecutedEvent {
+        owner: position.owner,
+        market_index: position.market_index,
+        position_id: position.position_id,
+        executor: ctx.accounts.executor.key(),
+        trigger_price: current_price,
+        fill_price: closed.fill_price,
+        pnl: closed.pnl,
+        execution_fee,
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct ExecuteTrigger<'info> {
+    #[account(
+        mut,
+        constraint = executor.key() != position.owner @ PerpsError::Unauthorized,
+    )]
+    pub executor: Signer<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = executor,
+        space = UserVault::LEN,
+        seeds = [USER_VAULT_SEED, executor.key().as_ref()],
+        bump,
+    )]
+    pub executor_vault: Account<'info, UserVault>,
+
+    #[account(
+        mut,
+        constraint = position.is_open @ PerpsError::PositionNotOpen,
+    )]
+    pub position: Account<'info, Position>,
+
+    #[account(
+        mut,
+        seeds = [USER_VAULT_SEED, position.owner.as_ref()],
+        bump = owner_vault.bump,
+    )]
+    pub owner_vault: Account<'info, UserVault>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATE_SEED],
+        bump = global_state.bump,
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(
+        mut,
+        seeds = [MARKET_SEED, position.market_index.to_le_bytes().as_ref()],
+        bump = market.bump,
+        has_one = price_feed @ PerpsError::InvalidOracle,
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [INSURANCE_FUND_SEED],
+        bump = insurance_fund.bump,
+    )]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+
+    #[account(
+        mut,
+        seeds = [LIQUIDITY_POOL_SEED],
+        bump = liquidity_pool.bump,
+    )]
+    pub liquidity_pool: Account<'info, LiquidityPool>,
+
+    /// CHECK: must be the market's configured oracle; parsed by `read_oracle_price`
+    pub price_feed: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
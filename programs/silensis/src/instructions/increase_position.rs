@@ -0,0 +1,202 @@
+use anchor_lang::prelude::*;
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::margin::cross_margin_headroom;
+use crate::math::calculate_notional;
+use crate::oracle::read_oracle_price;
+use crate::settlement::{settle_fill, OpenTrade, OpenedPosition};
+use crate::state::{GlobalState, InsuranceFund, LiquidityPool, Market, Position, UserVault};
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct IncreasePositionParams {
+    pub size: u64,
+    pub leverage: u64,
+}
+
+/// Remaining accounts, for a cross-margin vault: a (position, market, price
+/// feed) triple for each of the user's open positions, this one included.
+pub fn handle_increase_position<'info>(
+    ctx: Context<'_, '_, 'info, 'info, IncreasePosition<'info>>,
+    params: IncreasePositionParams,
+) -> Result<()> {
+    let global = &ctx.accounts.global_state;
+    require!(!global.is_paused, PerpsError::ProtocolPaused);
+    require!(params.size > 0, PerpsError::ZeroSize);
+    let market = &ctx.accounts.market;
+    require!(
+        params.leverage > 0 && params.leverage <= market.max_leverage,
+        PerpsError::InvalidLeverage
+    );
+
+    // Get current price from oracle
+    let clock = Clock::get()?;
+    let current_price = read_oracle_price(
+        &ctx.accounts.market,
+        &ctx.accounts.price_feed,
+        clock.unix_timestamp,
+    )?;
+
+    let cross_headroom = cross_margin_headroom(
+        &ctx.accounts.user_vault,
+        ctx.remaining_accounts,
+        clock.unix_timestamp,
+        read_oracle_price,
+    )?;
+
+    // Fill the added size, lock its margin and charge the taker fee
+    let trade = OpenTrade {
+        owner: ctx.accounts.position.owner,
+        direction: ctx.accounts.position.direction,
+        size: params.size,
+        leverage: params.leverage,
+        oracle_price: current_price,
+        cross_headroom,
+    };
+    let OpenedPosition {
+        fill_price,
+        notional: added_notional,
+        margin: added_margin,
+        ..
+    } = settle_fill(
+        &mut ctx.accounts.user_vault,
+        &mut ctx.accounts.global_state,
+        &mut ctx.accounts.market,
+        &mut ctx.accounts.insurance_fund,
+        &mut ctx.accounts.liquidity_pool,
+        &trade,
+    )?;
+    let market = &ctx.accounts.market;
+
+    let position = &ctx.accounts.position;
+    let new_size = position
+        .size
+        .checked_add(params.size)
+        .ok_or(PerpsError::MathOverflow)?;
+    let new_margin = position
+        .margin
+        .checked_add(added_margin)
+        .ok_or(PerpsError::MathOverflow)?;
+
+    // Size-weighted entry price
+    // entry = (old_size * old_entry + added_size * price) / new_size
+    let new_entry_price = (position.size as u128)
+        .checked_mul(position.entry_price as u128)
+        .ok_or(PerpsError::MathOverflow)?
+        .checked_add(
+            (params.size as u128)
+                .checked_mul(fill_price as u128)
+                .ok_or(PerpsError::MathOverflow)?,
+        )
+        .ok_or(PerpsError::MathOverflow)?
+        .checked_div(new_size as u128)
+        .ok_or(PerpsError::MathOverflow)? as u64;
+
+    // Revalidate leverage of the combined position
+    let new_notional = calculate_notional(new_size, new_entry_price)?;
+    require!(new_margin > 0, PerpsError::InsufficientMargin);
+    let new_leverage = new_notional
+        .checked_div(new_margin)
+        .ok_or(PerpsError::MathOverflow)?;
+    require!(
+        new_leverage <= market.max_leverage,
+        PerpsError::MaxLeverageExceeded
+    );
+    market.check_position_notional(new_notional)?;
+
+    // Blend the funding snapshot by notional so the existing size keeps owing
+    // funding from its original snapshot and the added size from now.
+    // snapshot = (old_notional * old_snapshot + added_notional * current_index) / new_notional
+    let old_notional = calculate_notional(position.size, position.entry_price)?;
+    let blended_notional = (old_notional as i128)
+        .checked_add(added_notional as i128)
+        .ok_or(PerpsError::MathOverflow)?;
+    let current_index = market.cumulative_funding_rate(position.direction);
+    let new_cumulative_funding = if blended_notional == 0 {
+        current_index
+    } else {
+        (old_notional as i128)
+            .checked_mul(position.cumulative_funding)
+            .ok_or(PerpsError::MathOverflow)?
+            .checked_add(
+                (added_notional as i128)
+                    .checked_mul(current_index)
+                    .ok_or(PerpsError::MathOverflow)?,
+            )
+            .ok_or(PerpsError::MathOverflow)?
+            .checked_div(blended_notional)
+            .ok_or(PerpsError::MathOverflow)?
+    };
+
+    // Grow the position
+    let position = &mut ctx.accounts.position;
+    position.update_trailing_price(current_price);
+    position.size = new_size;
+    position.entry_price = new_entry_price;
+    position.margin = new_margin;
+    position.leverage = new_leverage;
+    position.cumulative_funding = new_cumulative_funding;
+
+    msg!(
+        "Position {} increased by {}. Entry price: {}, Margin: {}, Leverage: {}",
+        position.position_id,
+        params.size,
+        new_entry_price,
+        new_margin,
+        new_leverage
+    );
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct IncreasePosition<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [USER_VAULT_SEED, user.key().as_ref()],
+        bump = user_vault.bump,
+        constraint = user_vault.owner == user.key() @ PerpsError::Unauthorized,
+    )]
+    pub user_vault: Account<'info, UserVault>,
+
+    #[account(
+        mut,
+        constraint = position.owner == user.key() @ PerpsError::Unauthorized,
+        constraint = position.is_open @ PerpsError::PositionNotOpen,
+    )]
+    pub position: Account<'info, Position>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATE_SEED],
+        bump = global_state.bump,
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(
+        mut,
+        seeds = [MARKET_SEED, position.market_index.to_le_bytes().as_ref()],
+        bump = market.bump,
+        has_one = price_feed @ PerpsError::InvalidOracle,
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [INSURANCE_FUND_SEED],
+        bump = insurance_fund.bump,
+    )]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+
+    #[account(
+        mut,
+        seeds = [LIQUIDITY_POOL_SEED],
+        bump = liquidity_pool.bump,
+    )]
+    pub liquidity_pool: Account<'info, LiquidityPool>,
+
+    /// CHECK: must be the market's configured oracle; parsed by `read_oracle_price`
+    pub price_feed: UncheckedAccount<'info>,
+}
@@ -1,30 +1,36 @@
 use anchor_lang::prelude::*;
 use anchor_spl::token::{Mint, Token, TokenAccount};
 use crate::constants::*;
-use crate::state::{GlobalState, PriceFeed};
+use crate::state::{GlobalState, InsuranceFund, LiquidityPool};
 
 pub fn handle_initialize(ctx: Context<Initialize>) -> Result<()> {
     let global = &mut ctx.accounts.global_state;
     global.authority = ctx.accounts.authority.key();
+    global.pending_authority = Pubkey::default();
+    global.guardian = ctx.accounts.authority.key();
     global.usdc_mint = ctx.accounts.usdc_mint.key();
     global.treasury = ctx.accounts.treasury.key();
-    global.total_long_oi = 0;
-    global.total_short_oi = 0;
-    global.last_funding_time = Clock::get()?.unix_timestamp;
-    global.cumulative_funding_rate_long = 0;
-    global.cumulative_funding_rate_short = 0;
-    global.max_leverage = MAX_LEVERAGE;
-    global.maintenance_margin_bps = MAINTENANCE_MARGIN_BPS;
-    global.liquidation_fee_bps = LIQUIDATION_FEE_BPS;
     global.next_position_id = 0;
+    global.next_order_id = 0;
+    global.protocol_fee_balance = 0;
+    global.total_bad_debt = 0;
+    global.max_user_notional = DEFAULT_MAX_USER_NOTIONAL;
+    global.market_count = 0;
     global.is_paused = false;
     global.bump = ctx.bumps.global_state;
 
-    let price_feed = &mut ctx.accounts.price_feed;
-    price_feed.authority = ctx.accounts.authority.key();
-    price_feed.price = 0;
-    price_feed.timestamp = 0;
-    price_feed.bump = ctx.bumps.price_feed;
+    let insurance_fund = &mut ctx.accounts.insurance_fund;
+    insurance_fund.balance = 0;
+    insurance_fund.fee_share_bps = INSURANCE_FEE_SHARE_BPS;
+    insurance_fund.total_shortfall_covered = 0;
+    insurance_fund.bump = ctx.bumps.insurance_fund;
+
+    let liquidity_pool = &mut ctx.accounts.liquidity_pool;
+    liquidity_pool.lp_mint = ctx.accounts.lp_mint.key();
+    liquidity_pool.balance = 0;
+    liquidity_pool.fee_share_bps = LP_FEE_SHARE_BPS;
+    liquidity_pool.total_fees_earned = 0;
+    liquidity_pool.bump = ctx.bumps.liquidity_pool;
 
     Ok(())
 }
@@ -58,11 +64,31 @@ pub struct Initialize<'info> {
     #[account(
         init,
         payer = authority,
-        space = PriceFeed::LEN,
-        seeds = [PRICE_FEED_SEED],
+        space = InsuranceFund::LEN,
+        seeds = [INSURANCE_FUND_SEED],
+        bump,
+    )]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = LiquidityPool::LEN,
+        seeds = [LIQUIDITY_POOL_SEED],
+        bump,
+    )]
+    pub liquidity_pool: Account<'info, LiquidityPool>,
+
+    /// LP share token, minted by the pool
+    #[account(
+        init,
+        payer = authority,
+        mint::decimals = usdc_mint.decimals,
+        mint::authority = liquidity_pool,
+        seeds = [LP_MINT_SEED],
         bump,
     )]
-    pub price_feed: Account<'info, PriceFeed>,
+    pub lp_mint: Account<'info, Mint>,
 
     pub system_program: Program<'info, System>,
     pub token_program: Program<'info, Token>,
@@ -0,0 +1,103 @@
+use anchor_lang::prelude::*;
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::state::{GlobalState, Market, PriceFeed, RiskPriceSource};
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct InitializeMarketParams {
+    pub max_leverage: u64,
+    pub maintenance_margin_bps: u64,
+    pub liquidation_fee_bps: u64,
+    pub full_liquidation_margin_bps: u64,
+    pub taker_fee_bps: u64,
+    pub publishers: Vec<Pubkey>, // initial price publishers, at most MAX_PRICE_PUBLISHERS
+    pub min_publishers: u8,      // fresh submissions required for a price
+}
+
+pub fn handle_initialize_market(
+    ctx: Context<InitializeMarket>,
+    params: InitializeMarketParams,
+) -> Result<()> {
+    Market::validate_risk_params(
+        params.max_leverage,
+        params.maintenance_margin_bps,
+        params.liquidation_fee_bps,
+        params.full_liquidation_margin_bps,
+    )?;
+    Market::validate_fee_params(params.taker_fee_bps)?;
+
+    let global = &mut ctx.accounts.global_state;
+    let market_index = global.market_count;
+
+    let price_feed = &mut ctx.accounts.price_feed;
+    price_feed.set_publishers(&params.publishers, params.min_publishers)?;
+    price_feed.price = 0;
+    price_feed.timestamp = 0;
+    price_feed.bump = ctx.bumps.price_feed;
+
+    let market = &mut ctx.accounts.market;
+    market.market_index = market_index;
+    market.price_feed = price_feed.key();
+    market.total_long_oi = 0;
+    market.total_short_oi = 0;
+    market.last_funding_time = Clock::get()?.unix_timestamp;
+    market.cumulative_funding_rate_long = 0;
+    market.cumulative_funding_rate_short = 0;
+    market.max_leverage = params.max_leverage;
+    market.maintenance_margin_bps = params.maintenance_margin_bps;
+    market.liquidation_fee_bps = params.liquidation_fee_bps;
+    market.full_liquidation_margin_bps = params.full_liquidation_margin_bps;
+    market.taker_fee_bps = params.taker_fee_bps;
+    market.risk_price_source = RiskPriceSource::Spot;
+    market.twap_window = DEFAULT_TWAP_WINDOW;
+    market.skew_scale = DEFAULT_SKEW_SCALE;
+    market.max_funding_rate_bps = DEFAULT_MAX_FUNDING_RATE_BPS;
+    market.max_open_interest = DEFAULT_MAX_OPEN_INTEREST;
+    market.max_long_oi = DEFAULT_MAX_SIDE_OPEN_INTEREST;
+    market.max_short_oi = DEFAULT_MAX_SIDE_OPEN_INTEREST;
+    market.max_position_notional = DEFAULT_MAX_POSITION_NOTIONAL;
+    market.bump = ctx.bumps.market;
+
+    global.market_count = global
+        .market_count
+        .checked_add(1)
+        .ok_or(PerpsError::MathOverflow)?;
+
+    msg!("Market {} initialized", market_index);
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct InitializeMarket<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATE_SEED],
+        bump = global_state.bump,
+        has_one = authority @ PerpsError::Unauthorized,
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = Market::LEN,
+        seeds = [MARKET_SEED, global_state.market_count.to_le_bytes().as_ref()],
+        bump,
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = PriceFeed::LEN,
+        seeds = [PRICE_FEED_SEED, global_state.market_count.to_le_bytes().as_ref()],
+        bump,
+    )]
+    pub price_feed: Box<Account<'info, PriceFeed>>,
+
+    pub system_program: Program<'info, System>,
+}
@@ -1,31 +1,50 @@
 use anchor_lang::prelude::*;
 use crate::constants::*;
 use crate::errors::PerpsError;
-use crate::math::{calculate_margin_ratio, calculate_pnl};
-use crate::state::{Direction, GlobalState, PriceFeed, Position, UserVault};
+use crate::math::{
+    calculate_accrued_funding, calculate_fee, calculate_margin_ratio, calculate_notional,
+    calculate_partial_liquidation_size, calculate_pnl,
+};
+use crate::oracle::read_risk_price;
+use crate::settlement::settle_position_pnl;
+use crate::state::{
+    GlobalState, InsuranceFund, LiquidityPool, MarginMode, Market, Position, PricingMode, UserVault,
+};
 
 pub fn handle_liquidate(ctx: Context<Liquidate>) -> Result<()> {
     let position = &ctx.accounts.position;
     require!(position.is_open, PerpsError::PositionNotOpen);
 
-    // Get current price from oracle
-    let price_feed = &ctx.accounts.price_feed;
+    // Get the market's risk price (spot, TWAP or EMA) from oracle
     let clock = Clock::get()?;
-    require!(
-        clock.unix_timestamp - price_feed.timestamp <= MAX_ORACLE_STALENESS,
-        PerpsError::OracleStale
-    );
-    require!(price_feed.price > 0, PerpsError::OracleInvalidPrice);
-    let current_price = price_feed.price;
+    let current_price = read_risk_price(
+        &ctx.accounts.market,
+        &ctx.accounts.price_feed,
+        clock.unix_timestamp,
+    )?;
+
+    // Bring the funding index up to date before settling against it
+    ctx.accounts.market.accrue_funding(clock.unix_timestamp)?;
 
-    // Calculate PnL and margin ratio
-    let pnl = calculate_pnl(
+    // Calculate PnL net of funding accrued since open, and margin ratio
+    let price_pnl = calculate_pnl(
         position.direction,
         position.size,
         position.entry_price,
         current_price,
     )?;
 
+    let funding_payment = calculate_accrued_funding(
+        position.size,
+        position.entry_price,
+        ctx.accounts.market.cumulative_funding_rate(position.direction),
+        position.cumulative_funding,
+    )?;
+
+    let pnl = price_pnl
+        .checked_sub(funding_payment)
+        .ok_or(PerpsError::MathOverflow)?;
+
     let margin_ratio = calculate_margin_ratio(
         position.margin,
         pnl,
@@ -34,90 +53,194 @@ pub fn handle_liquidate(ctx: Context<Liquidate>) -> Result<()> {
     )?;
 
     // Position must be below maintenance margin
-    let global = &ctx.accounts.global_state;
+    let market = &ctx.accounts.market;
     require!(
-        margin_ratio < global.maintenance_margin_bps,
+        margin_ratio < market.maintenance_margin_bps,
         PerpsError::PositionNotLiquidatable
     );
 
     let margin = position.margin;
 
-    // Calculate liquidation fee
-    let liq_fee = (margin as u128)
-        .checked_mul(global.liquidation_fee_bps as u128)
-        .ok_or(PerpsError::MathOverflow)?
-        .checked_div(BPS_PRECISION as u128)
-        .ok_or(PerpsError::MathOverflow)? as u64;
+    // Below the full-liquidation threshold the whole position is closed.
+    // Otherwise only enough size is closed to bring the position back above
+    // maintenance margin plus a buffer.
+    let effective_margin = (margin as i64)
+        .checked_add(pnl)
+        .ok_or(PerpsError::MathOverflow)?;
+    let liquidation_size = if margin_ratio < market.full_liquidation_margin_bps {
+        position.size
+    } else {
+        calculate_partial_liquidation_size(
+            position.size,
+            margin,
+            effective_margin,
+            current_price,
+            market
+                .maintenance_margin_bps
+                .checked_add(LIQUIDATION_BUFFER_BPS)
+                .ok_or(PerpsError::MathOverflow)?,
+            market.liquidation_fee_bps,
+        )?
+    };
+    require!(liquidation_size > 0, PerpsError::PositionNotLiquidatable);
+    let is_full = liquidation_size >= position.size;
 
-    // Calculate notional for OI update
-    let notional = (position.size as u128)
-        .checked_mul(position.entry_price as u128)
+    // Liquidation fee is proportional to the margin of the liquidated slice
+    let margin_slice = (margin as u128)
+        .checked_mul(liquidation_size as u128)
         .ok_or(PerpsError::MathOverflow)?
-        .checked_div(SIZE_PRECISION as u128)
+        .checked_div(position.size as u128)
         .ok_or(PerpsError::MathOverflow)? as u64;
+    let liq_fee = calculate_fee(margin_slice, market.liquidation_fee_bps)?;
 
-    // Effective margin after PnL
-    let effective_margin = if pnl >= 0 {
-        margin
-            .checked_add(pnl as u64)
-            .ok_or(PerpsError::MathOverflow)?
+    // Calculate liquidated notional for OI update
+    let notional = calculate_notional(liquidation_size, position.entry_price)?;
+
+    // A vAMM market unwinds the liquidated size through its reserves and
+    // settles at that fill, so the pool books what the reserves moved;
+    // otherwise the liquidation settles at the risk price
+    let settle_price = if ctx.accounts.market.pricing_mode == PricingMode::Vamm {
+        ctx.accounts.market.execute_trade(
+            current_price,
+            position.direction.opposite(),
+            liquidation_size,
+        )?
     } else {
-        margin.saturating_sub((-pnl) as u64)
+        current_price
     };
+    let market = &ctx.accounts.market;
 
-    // Remaining margin after liquidation fee goes back to position owner
-    let remaining = effective_margin.saturating_sub(liq_fee);
-
-    // Update owner vault: unlock margin and adjust balance
+    let liquidity_pool = &ctx.accounts.liquidity_pool;
     let owner_vault = &mut ctx.accounts.owner_vault;
-    owner_vault.locked_margin = owner_vault
-        .locked_margin
-        .checked_sub(margin)
-        .ok_or(PerpsError::MathOverflow)?;
-
-    // Owner gets remaining after fee; adjust deposited_amount
-    // deposited_amount was originally = X, locked_margin had `margin` locked
-    // Now: unlocked, but the position lost value. New deposited = deposited - margin + remaining
-    if remaining >= margin {
-        owner_vault.deposited_amount = owner_vault
-            .deposited_amount
-            .checked_add(remaining - margin)
+    let (remaining, shortfall, realized_pnl) = if is_full {
+        // Settle the owner's margin against PnL less the liquidation fee. The
+        // owner keeps what is left; a loss or fee the margin cannot pay is a shortfall.
+        let pnl = liquidity_pool.cap_trader_pnl(
+            calculate_pnl(
+                position.direction,
+                position.size,
+                position.entry_price,
+                settle_price,
+            )?
+            .checked_sub(funding_payment)
+            .ok_or(PerpsError::MathOverflow)?,
+        );
+        let pnl_after_fee = pnl
+            .checked_sub(liq_fee as i64)
             .ok_or(PerpsError::MathOverflow)?;
+        let (remaining, shortfall) = owner_vault.settle_margin(margin, pnl_after_fee)?;
+        owner_vault.open_positions = owner_vault.open_positions.saturating_sub(1);
+        (remaining, shortfall, pnl)
     } else {
-        owner_vault.deposited_amount = owner_vault
-            .deposited_amount
-            .saturating_sub(margin - remaining);
-    }
+        // Realize PnL and funding on the slice against the position's margin;
+        // the remaining position keeps all remaining margin.
+        let slice_pnl = liquidity_pool.cap_trader_pnl(
+            calculate_pnl(
+                position.direction,
+                liquidation_size,
+                position.entry_price,
+                settle_price,
+            )?
+            .checked_sub(calculate_accrued_funding(
+                liquidation_size,
+                position.entry_price,
+                market.cumulative_funding_rate(position.direction),
+                position.cumulative_funding,
+            )?)
+            .ok_or(PerpsError::MathOverflow)?,
+        );
 
-    // Award liquidation fee to liquidator
-    let liquidator_vault = &mut ctx.accounts.liquidator_vault;
-    liquidator_vault.owner = ctx.accounts.liquidator.key();
-    liquidator_vault.deposited_amount = liquidator_vault
-        .deposited_amount
-        .checked_add(liq_fee)
-        .ok_or(PerpsError::MathOverflow)?;
+        let new_margin = (margin as i64)
+            .checked_add(slice_pnl)
+            .ok_or(PerpsError::MathOverflow)?
+            .checked_sub(liq_fee as i64)
+            .ok_or(PerpsError::MathOverflow)?;
+        require!(new_margin > 0, PerpsError::InsufficientMargin);
+        let new_margin = new_margin as u64;
 
-    // Update global state open interest
-    let global = &mut ctx.accounts.global_state;
-    match position.direction {
-        Direction::Long => {
-            global.total_long_oi = global.total_long_oi.saturating_sub(notional);
-        }
-        Direction::Short => {
-            global.total_short_oi = global.total_short_oi.saturating_sub(notional);
+        // Move the realized change in margin out of (or into) the vault
+        if new_margin < margin {
+            let realized_loss = margin - new_margin;
+            owner_vault.locked_margin = owner_vault
+                .locked_margin
+                .checked_sub(realized_loss)
+                .ok_or(PerpsError::MathOverflow)?;
+            owner_vault.deposited_amount = owner_vault
+                .deposited_amount
+                .checked_sub(realized_loss)
+                .ok_or(PerpsError::MathOverflow)?;
+        } else {
+            let realized_gain = new_margin - margin;
+            owner_vault.locked_margin = owner_vault
+                .locked_margin
+                .checked_add(realized_gain)
+                .ok_or(PerpsError::MathOverflow)?;
+            owner_vault.deposited_amount = owner_vault
+                .deposited_amount
+                .checked_add(realized_gain)
+                .ok_or(PerpsError::MathOverflow)?;
         }
-    }
 
-    // Mark position as closed
+        (new_margin, 0, slice_pnl)
+    };
+    owner_vault.decrease_open_notional(notional);
+
+    // Award liquidation fee to liquidator, less the insurance fund's share
+    let liquidator_fee = ctx.accounts.insurance_fund.take_fee_share(liq_fee)?;
+    ctx.accounts.liquidator_vault.credit(
+        ctx.accounts.liquidator.key(),
+        ctx.bumps.liquidator_vault,
+        liquidator_fee,
+    )?;
+
+    // Cover any shortfall from the insurance fund; the pool takes the rest
+    settle_position_pnl(
+        position,
+        &mut ctx.accounts.global_state,
+        &mut ctx.accounts.insurance_fund,
+        &mut ctx.accounts.liquidity_pool,
+        realized_pnl,
+        shortfall,
+    )?;
+
+    // Update market open interest
+    ctx.accounts
+        .market
+        .decrease_open_interest(position.direction, liquidation_size, notional);
+
     let position = &mut ctx.accounts.position;
-    position.is_open = false;
+    if is_full {
+        // Mark position as closed
+        position.is_open = false;
 
-    msg!(
-        "Position {} liquidated. Fee: {}, Remaining: {}",
-        position.position_id,
-        liq_fee,
-        remaining
-    );
+        msg!(
+            "Position {} liquidated. Fee: {}, Funding: {}, Remaining: {}, Shortfall: {}",
+            position.position_id,
+            liq_fee,
+            funding_payment,
+            remaining,
+            shortfall
+        );
+    } else {
+        // Shrink the position; entry price and funding snapshot carry over
+        position.update_trailing_price(current_price);
+        position.size = position
+            .size
+            .checked_sub(liquidation_size)
+            .ok_or(PerpsError::MathOverflow)?;
+        position.margin = remaining;
+        position.leverage = calculate_notional(position.size, position.entry_price)?
+            .checked_div(remaining)
+            .ok_or(PerpsError::MathOverflow)?;
+
+        msg!(
+            "Position {} partially liquidated. Size: {}, Fee: {}, Margin: {}",
+            position.position_id,
+            liquidation_size,
+            liq_fee,
+            remaining
+        );
+    }
 
     Ok(())
 }
@@ -146,6 +269,7 @@ pub struct Liquidate<'info> {
         mut,
         seeds = [USER_VAULT_SEED, position.owner.as_ref()],
         bump = owner_vault.bump,
+        constraint = owner_vault.margin_mode == MarginMode::Isolated @ PerpsError::MarginModeMismatch,
     )]
     pub owner_vault: Account<'info, UserVault>,
 
@@ -157,10 +281,29 @@ pub struct Liquidate<'info> {
     pub global_state: Account<'info, GlobalState>,
 
     #[account(
-        seeds = [PRICE_FEED_SEED],
-        bump = price_feed.bump,
+        mut,
+        seeds = [MARKET_SEED, position.market_index.to_le_bytes().as_ref()],
+        bump = market.bump,
+        has_one = price_feed @ PerpsError::InvalidOracle,
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [INSURANCE_FUND_SEED],
+        bump = insurance_fund.bump,
+    )]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+
+    #[account(
+        mut,
+        seeds = [LIQUIDITY_POOL_SEED],
+        bump = liquidity_pool.bump,
     )]
-    pub price_feed: Account<'info, PriceFeed>,
+    pub liquidity_pool: Account<'info, LiquidityPool>,
+
+    /// CHECK: must be the market's configured oracle; parsed by `read_oracle_price`
+    pub price_feed: UncheckedAccount<'info>,
 
     pub system_program: Program<'info, System>,
 }
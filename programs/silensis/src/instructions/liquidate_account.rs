@@ -0,0 +1,178 @@
+use anchor_lang::prelude::*;
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::events::AccountLiquidatedEvent;
+use crate::margin::AccountHealth;
+use crate::math::{calculate_fee, calculate_notional, calculate_pnl};
+use crate::oracle::read_risk_price;
+use crate::settlement::settle_pool_pnl;
+use crate::state::{GlobalState, InsuranceFund, LiquidityPool, MarginMode, PricingMode, UserVault};
+
+/// Liquidate a cross-margin account whose equity is below the summed
+/// maintenance margin of its positions. Every open position is closed.
+/// Remaining accounts: a writable (position, market, price feed) triple
+/// for each of the owner's open positions.
+pub fn handle_liquidate_account<'info>(
+    ctx: Context<'_, '_, 'info, 'info, LiquidateAccount<'info>>,
+) -> Result<()> {
+    let clock = Clock::get()?;
+    let mut health = AccountHealth::load(
+        &ctx.accounts.owner_vault,
+        ctx.remaining_accounts,
+        clock.unix_timestamp,
+        read_risk_price,
+    )?;
+    require!(health.is_liquidatable(), PerpsError::AccountNotLiquidatable);
+
+    // Close every position, charging the liquidation fee on each one's margin
+    let mut total_margin: u64 = 0;
+    let mut total_pnl: i64 = 0;
+    let mut total_fee: u64 = 0;
+    for valuation in health.positions.iter_mut() {
+        let market = &mut health.markets[valuation.market];
+        require!(
+            valuation.position.to_account_info().is_writable
+                && market.to_account_info().is_writable,
+            PerpsError::InvalidRemainingAccounts
+        );
+
+        let position = &mut valuation.position;
+        total_margin = total_margin
+            .checked_add(position.margin)
+            .ok_or(PerpsError::MathOverflow)?;
+        total_fee = total_fee
+            .checked_add(calculate_fee(position.margin, market.liquidation_fee_bps)?)
+            .ok_or(PerpsError::MathOverflow)?;
+
+        // A vAMM position unwinds through the reserves and settles at that
+        // fill rather than the risk price, so the pool books what they moved
+        let mut pnl = valuation.pnl;
+        if market.pricing_mode == PricingMode::Vamm {
+            let fill_price = market.execute_trade(
+                valuation.current_price,
+                position.direction.opposite(),
+                position.size,
+            )?;
+            let price_pnl = |price| {
+                calculate_pnl(position.direction, position.size, position.entry_price, price)
+            };
+            pnl = pnl
+                .checked_add(price_pnl(fill_price)?)
+                .ok_or(PerpsError::MathOverflow)?
+                .checked_sub(price_pnl(valuation.current_price)?)
+                .ok_or(PerpsError::MathOverflow)?;
+        }
+        total_pnl = total_pnl
+            .checked_add(pnl)
+            .ok_or(PerpsError::MathOverflow)?;
+
+        let notional = calculate_notional(position.size, position.entry_price)?;
+        market.decrease_open_interest(position.direction, position.size, notional);
+
+        position.is_open = false;
+        position.exit(&crate::ID)?;
+    }
+    for market in health.markets.iter() {
+        market.exit(&crate::ID)?;
+    }
+
+    // Settle all margin against the combined PnL less fees, paying profit
+    // only as far as the pool can; in cross mode losses beyond margin are
+    // taken from the rest of the vault first
+    let total_pnl = ctx.accounts.liquidity_pool.cap_trader_pnl(total_pnl);
+    let pnl_after_fee = total_pnl
+        .checked_sub(total_fee as i64)
+        .ok_or(PerpsError::MathOverflow)?;
+    let owner_vault = &mut ctx.accounts.owner_vault;
+    let (remaining, shortfall) = owner_vault.settle_margin(total_margin, pnl_after_fee)?;
+    let positions_closed = owner_vault.open_positions;
+    owner_vault.open_positions = 0;
+    owner_vault.open_notional = 0;
+
+    // Award liquidation fee to liquidator, less the insurance fund's share
+    let liquidator_fee = ctx.accounts.insurance_fund.take_fee_share(total_fee)?;
+    ctx.accounts.liquidator_vault.credit(
+        ctx.accounts.liquidator.key(),
+        ctx.bumps.liquidator_vault,
+        liquidator_fee,
+    )?;
+
+    // Cover any shortfall from the insurance fund; the pool takes the rest
+    let (covered, bad_debt) = settle_pool_pnl(
+        &mut ctx.accounts.global_state,
+        &mut ctx.accounts.insurance_fund,
+        &mut ctx.accounts.liquidity_pool,
+        total_pnl,
+        shortfall,
+    )?;
+
+    emit!(AccountLiquidatedEvent {
+        owner: ctx.accounts.owner_vault.owner,
+        positions_closed,
+        equity: health.equity,
+        maintenance_margin: health.maintenance_margin,
+        liquidation_fee: total_fee,
+        shortfall,
+        covered_by_insurance: covered,
+        bad_debt,
+        timestamp: clock.unix_timestamp,
+    });
+
+    msg!(
+        "Account {} liquidated. Positions: {}, Equity: {}, Fee: {}, Remaining: {}, Shortfall: {}",
+        ctx.accounts.owner_vault.owner,
+        positions_closed,
+        health.equity,
+        total_fee,
+        remaining,
+        shortfall
+    );
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct LiquidateAccount<'info> {
+    #[account(mut)]
+    pub liquidator: Signer<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = liquidator,
+        space = UserVault::LEN,
+        seeds = [USER_VAULT_SEED, liquidator.key().as_ref()],
+        bump,
+    )]
+    pub liquidator_vault: Account<'info, UserVault>,
+
+    #[account(
+        mut,
+        seeds = [USER_VAULT_SEED, owner_vault.owner.as_ref()],
+        bump = owner_vault.bump,
+        constraint = owner_vault.margin_mode == MarginMode::Cross @ PerpsError::MarginModeMismatch,
+    )]
+    pub owner_vault: Account<'info, UserVault>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATE_SEED],
+        bump = global_state.bump,
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(
+        mut,
+        seeds = [INSURANCE_FUND_SEED],
+        bump = insurance_fund.bump,
+    )]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+
+    #[account(
+        mut,
+        seeds = [LIQUIDITY_POOL_SEED],
+        bump = liquidity_pool.bump,
+    )]
+    pub liquidity_pool: Account<'info, LiquidityPool>,
+
+    pub system_program: Program<'info, System>,
+}
@@ -1,17 +1,73 @@
 pub mod initialize;
+pub mod initialize_market;
 pub mod set_price;
 pub mod deposit;
 pub mod withdraw;
 pub mod open_position;
 pub mod close_position;
+pub mod decrease_position;
+pub mod increase_position;
+pub mod add_margin;
+pub mod remove_margin;
 pub mod liquidate;
+pub mod liquidate_account;
 pub mod apply_funding;
+pub mod update_market;
+pub mod set_paused;
+pub mod propose_authority;
+pub mod accept_authority;
+pub mod set_guardian;
+pub mod set_price_publishers;
+pub mod set_market_oracle;
+pub mod sweep_fees;
+pub mod set_insurance_fee_share;
+pub mod set_margin_mode;
+pub mod set_max_user_notional;
+pub mod set_market_pricing;
+pub mod repeg_vamm;
+pub mod add_liquidity;
+pub mod remove_liquidity;
+pub mod set_lp_fee_share;
+pub mod place_order;
+pub mod cancel_order;
+pub mod close_expired_order;
+pub mod execute_order;
+pub mod set_position_triggers;
+pub mod execute_trigger;
 
 pub use initialize::*;
+pub use initialize_market::*;
 pub use set_price::*;
 pub use deposit::*;
 pub use withdraw::*;
 pub use open_position::*;
 pub use close_position::*;
+pub use decrease_position::*;
+pub use increase_position::*;
+pub use add_margin::*;
+pub use remove_margin::*;
 pub use liquidate::*;
+pub use liquidate_account::*;
 pub use apply_funding::*;
+pub use update_market::*;
+pub use set_paused::*;
+pub use propose_authority::*;
+pub use accept_authority::*;
+pub use set_guardian::*;
+pub use set_price_publishers::*;
+pub use set_market_oracle::*;
+pub use sweep_fees::*;
+pub use set_insurance_fee_share::*;
+pub use set_margin_mode::*;
+pub use set_max_user_notional::*;
+pub use set_market_pricing::*;
+pub use repeg_vamm::*;
+pub use add_liquidity::*;
+pub use remove_liquidity::*;
+pub use set_lp_fee_share::*;
+pub use place_order::*;
+pub use cancel_order::*;
+pub use close_expired_order::*;
+pub use execute_order::*;
+pub use set_position_triggers::*;
+pub use execute_trigger::*;
@@ -1,100 +1,108 @@
 use anchor_lang::prelude::*;
 use crate::constants::*;
 use crate::errors::PerpsError;
-use crate::state::{Direction, GlobalState, PriceFeed, Position, UserVault};
+use crate::margin::cross_margin_headroom;
+use crate::math::calculate_size;
+use crate::oracle::read_oracle_price;
+use crate::settlement::{settle_open, OpenTrade};
+use crate::state::{
+    Direction, GlobalState, InsuranceFund, LiquidityPool, Market, Position, UserVault,
+};
+
+/// Unit in which `OpenPositionParams::size` is given.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SizeMode {
+    #[default]
+    Base,     // base asset units (lamport precision)
+    Margin,   // USDC margin, opened at `leverage` times its value
+    Notional, // USDC notional
+}
 
 #[derive(AnchorSerialize, AnchorDeserialize)]
 pub struct OpenPositionParams {
     pub direction: Direction,
     pub size: u64,
+    pub size_mode: SizeMode,
     pub leverage: u64,
+    pub acceptable_price: Option<u64>, // max fill price for longs, min for shorts
+    pub expiry_timestamp: Option<i64>,
 }
 
-pub fn handle_open_position(
-    ctx: Context<OpenPosition>,
+/// Remaining accounts, for a cross-margin vault: a (position, market, price
+/// feed) triple for each of the user's open positions, so the new position is
+/// checked against account equity.
+pub fn handle_open_position<'info>(
+    ctx: Context<'_, '_, 'info, 'info, OpenPosition<'info>>,
     params: OpenPositionParams,
 ) -> Result<()> {
     let global = &ctx.accounts.global_state;
     require!(!global.is_paused, PerpsError::ProtocolPaused);
     require!(params.size > 0, PerpsError::ZeroSize);
+    let market = &ctx.accounts.market;
     require!(
-        params.leverage > 0 && params.leverage <= global.max_leverage,
+        params.leverage > 0 && params.leverage <= market.max_leverage,
         PerpsError::InvalidLeverage
     );
 
-    // Get current price from oracle
-    let price_feed = &ctx.accounts.price_feed;
     let clock = Clock::get()?;
-    require!(
-        clock.unix_timestamp - price_feed.timestamp <= MAX_ORACLE_STALENESS,
-        PerpsError::OracleStale
-    );
-    require!(price_feed.price > 0, PerpsError::OracleInvalidPrice);
-    let current_price = price_feed.price;
-
-    // Calculate notional value and required margin
-    // notional = size * price / SIZE_PRECISION
-    let notional = (params.size as u128)
-        .checked_mul(current_price as u128)
-        .ok_or(PerpsError::MathOverflow)?
-        .checked_div(SIZE_PRECISION as u128)
-        .ok_or(PerpsError::MathOverflow)? as u64;
-
-    let required_margin = notional
-        .checked_div(params.leverage)
-        .ok_or(PerpsError::MathOverflow)?;
-
-    // Check available balance
-    let vault = &ctx.accounts.user_vault;
-    let available = vault
-        .deposited_amount
-        .checked_sub(vault.locked_margin)
-        .ok_or(PerpsError::MathOverflow)?;
-    require!(required_margin <= available, PerpsError::InsufficientMargin);
-
-    // Create position
-    let position = &mut ctx.accounts.position;
-    let global = &mut ctx.accounts.global_state;
-
-    position.owner = ctx.accounts.user.key();
-    position.position_id = global.next_position_id;
-    position.direction = params.direction;
-    position.size = params.size;
-    position.entry_price = current_price;
-    position.leverage = params.leverage;
-    position.margin = required_margin;
-    position.last_funding_time = clock.unix_timestamp;
-    position.cumulative_funding = 0;
-    position.is_open = true;
-    position.bump = ctx.bumps.position;
-
-    // Lock margin in vault
-    let vault = &mut ctx.accounts.user_vault;
-    vault.locked_margin = vault
-        .locked_margin
-        .checked_add(required_margin)
-        .ok_or(PerpsError::MathOverflow)?;
-
-    // Update open interest
-    match params.direction {
-        Direction::Long => {
-            global.total_long_oi = global
-                .total_long_oi
-                .checked_add(notional)
-                .ok_or(PerpsError::MathOverflow)?;
-        }
-        Direction::Short => {
-            global.total_short_oi = global
-                .total_short_oi
-                .checked_add(notional)
-                .ok_or(PerpsError::MathOverflow)?;
-        }
+    if let Some(expiry_timestamp) = params.expiry_timestamp {
+        require!(
+            clock.unix_timestamp <= expiry_timestamp,
+            PerpsError::TransactionExpired
+        );
     }
 
-    global.next_position_id = global
-        .next_position_id
-        .checked_add(1)
-        .ok_or(PerpsError::MathOverflow)?;
+    // Get current price from oracle
+    let current_price = read_oracle_price(
+        &ctx.accounts.market,
+        &ctx.accounts.price_feed,
+        clock.unix_timestamp,
+    )?;
+
+    // Sizes given in USDC convert to base units at the oracle price
+    let size = match params.size_mode {
+        SizeMode::Base => params.size,
+        SizeMode::Margin => calculate_size(
+            params
+                .size
+                .checked_mul(params.leverage)
+                .ok_or(PerpsError::MathOverflow)?,
+            current_price,
+        )?,
+        SizeMode::Notional => calculate_size(params.size, current_price)?,
+    };
+    require!(size > 0, PerpsError::ZeroSize);
+
+    let cross_headroom = cross_margin_headroom(
+        &ctx.accounts.user_vault,
+        ctx.remaining_accounts,
+        clock.unix_timestamp,
+        read_oracle_price,
+    )?;
+
+    let opened = settle_open(
+        &mut ctx.accounts.position,
+        &mut ctx.accounts.user_vault,
+        &mut ctx.accounts.global_state,
+        &mut ctx.accounts.market,
+        &mut ctx.accounts.insurance_fund,
+        &mut ctx.accounts.liquidity_pool,
+        OpenTrade {
+            owner: ctx.accounts.user.key(),
+            direction: params.direction,
+            size,
+            leverage: params.leverage,
+            oracle_price: current_price,
+            cross_headroom,
+        },
+    )?;
+    ctx.accounts.position.bump = ctx.bumps.position;
+    if let Some(acceptable_price) = params.acceptable_price {
+        require!(
+            params.direction.is_price_acceptable(opened.fill_price, acceptable_price),
+            PerpsError::SlippageExceeded
+        );
+    }
 
     Ok(())
 }
@@ -129,10 +137,29 @@ pub struct OpenPosition<'info> {
     pub global_state: Account<'info, GlobalState>,
 
     #[account(
-        seeds = [PRICE_FEED_SEED],
-        bump = price_feed.bump,
+        mut,
+        seeds = [MARKET_SEED, market.market_index.to_le_bytes().as_ref()],
+        bump = market.bump,
+        has_one = price_feed @ PerpsError::InvalidOracle,
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [INSURANCE_FUND_SEED],
+        bump = insurance_fund.bump,
+    )]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+
+    #[account(
+        mut,
+        seeds = [LIQUIDITY_POOL_SEED],
+        bump = liquidity_pool.bump,
     )]
-    pub price_feed: Account<'info, PriceFeed>,
+    pub liquidity_pool: Account<'info, LiquidityPool>,
+
+    /// CHECK: must be the market's configured oracle; parsed by `read_oracle_price`
+    pub price_feed: UncheckedAccount<'info>,
 
     pub system_program: Program<'info, System>,
 }
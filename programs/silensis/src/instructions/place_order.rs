@@ -0,0 +1,138 @@
+use anchor_lang::prelude::*;
+use anchor_lang::system_program::{self, Transfer};
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::events::OrderPlacedEvent;
+use crate::math::{calculate_fee, calculate_notional};
+use crate::state::{Direction, GlobalState, Market, Order, Position, UserVault};
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct PlaceOrderParams {
+    pub direction: Direction,
+    pub size: u64,
+    pub leverage: u64,
+    pub limit_price: u64,
+    pub expiry: i64,
+}
+
+pub fn handle_place_order(ctx: Context<PlaceOrder>, params: PlaceOrderParams) -> Result<()> {
+    let global = &ctx.accounts.global_state;
+    require!(!global.is_paused, PerpsError::ProtocolPaused);
+    require!(params.size > 0, PerpsError::ZeroSize);
+    require!(params.limit_price > 0, PerpsError::InvalidParameter);
+    let market = &ctx.accounts.market;
+    require!(
+        params.leverage > 0 && params.leverage <= market.max_leverage,
+        PerpsError::InvalidLeverage
+    );
+
+    let clock = Clock::get()?;
+    require!(params.expiry > clock.unix_timestamp, PerpsError::InvalidExpiry);
+
+    let notional = calculate_notional(params.size, params.limit_price)?;
+    market.check_position_notional(notional)?;
+
+    // Escrow the margin and the highest taker fee the position can need at
+    // its worst fill, plus the keeper's execution fee, so a fill never fails
+    // for want of balance
+    let worst_notional = calculate_notional(
+        params.size,
+        Order::worst_fill_price(params.direction, params.limit_price)?,
+    )?;
+    let escrow = worst_notional
+        .checked_div(params.leverage)
+        .ok_or(PerpsError::MathOverflow)?
+        .checked_add(calculate_fee(worst_notional, MAX_TAKER_FEE_BPS)?)
+        .ok_or(PerpsError::MathOverflow)?
+        .checked_add(ORDER_EXECUTION_FEE)
+        .ok_or(PerpsError::MathOverflow)?;
+
+    let vault = &mut ctx.accounts.user_vault;
+    require!(escrow <= vault.free_balance()?, PerpsError::InsufficientMargin);
+    vault.locked_margin = vault
+        .locked_margin
+        .checked_add(escrow)
+        .ok_or(PerpsError::MathOverflow)?;
+
+    let global = &mut ctx.accounts.global_state;
+    let order = &mut ctx.accounts.order;
+    order.owner = ctx.accounts.user.key();
+    order.market_index = market.market_index;
+    order.order_id = global.next_order_id;
+    order.direction = params.direction;
+    order.size = params.size;
+    order.limit_price = params.limit_price;
+    order.leverage = params.leverage;
+    order.escrow = escrow;
+    order.execution_fee = ORDER_EXECUTION_FEE;
+    order.expiry = params.expiry;
+    order.bump = ctx.bumps.order;
+
+    global.next_order_id = global
+        .next_order_id
+        .checked_add(1)
+        .ok_or(PerpsError::MathOverflow)?;
+
+    // Prefund the rent of the position the keeper creates on execution;
+    // the order account's lamports go to the keeper when it fills
+    let position_rent = Rent::get()?.minimum_balance(Position::LEN);
+    let cpi_accounts = Transfer {
+        from: ctx.accounts.user.to_account_info(),
+        to: ctx.accounts.order.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(ctx.accounts.system_program.to_account_info(), cpi_accounts);
+    system_program::transfer(cpi_ctx, position_rent)?;
+
+    let order = &ctx.accounts.order;
+    emit!(OrderPlacedEvent {
+        owner: order.owner,
+        market_index: order.market_index,
+        order_id: order.order_id,
+        direction: order.direction,
+        size: order.size,
+        limit_price: order.limit_price,
+        leverage: order.leverage,
+        expiry: order.expiry,
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct PlaceOrder<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [USER_VAULT_SEED, user.key().as_ref()],
+        bump = user_vault.bump,
+        constraint = user_vault.owner == user.key() @ PerpsError::Unauthorized,
+    )]
+    pub user_vault: Account<'info, UserVault>,
+
+    #[account(
+        init,
+        payer = user,
+        space = Order::LEN,
+        seeds = [ORDER_SEED, user.key().as_ref(), global_state.next_order_id.to_le_bytes().as_ref()],
+        bump,
+    )]
+    pub order: Account<'info, Order>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATE_SEED],
+        bump = global_state.bump,
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(
+        seeds = [MARKET_SEED, market.market_index.to_le_bytes().as_ref()],
+        bump = market.bump,
+    )]
+    pub market: Account<'info, Market>,
+
+    pub system_program: Program<'info, System>,
+}
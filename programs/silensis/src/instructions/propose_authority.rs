@@ -0,0 +1,29 @@
+use anchor_lang::prelude::*;
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::state::GlobalState;
+
+pub fn handle_propose_authority(
+    ctx: Context<ProposeAuthority>,
+    new_authority: Pubkey,
+) -> Result<()> {
+    let global = &mut ctx.accounts.global_state;
+    global.pending_authority = new_authority;
+
+    msg!("Authority transfer proposed to {}", new_authority);
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct ProposeAuthority<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATE_SEED],
+        bump = global_state.bump,
+        has_one = authority @ PerpsError::Unauthorized,
+    )]
+    pub global_state: Account<'info, GlobalState>,
+}
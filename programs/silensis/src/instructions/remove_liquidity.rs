@@ -0,0 +1,120 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token::{self, Burn, Mint, Token, TokenAccount, Transfer};
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::events::LiquidityChangedEvent;
+use crate::pool::load_pool_nav;
+use crate::state::{GlobalState, LiquidityPool};
+
+/// Burn share tokens for their USDC value at NAV. Only the pool's realized
+/// balance can be paid out.
+/// Remaining accounts: a (market, price feed) pair for every listed market,
+/// in market index order, so unrealized trader PnL is reflected in NAV.
+pub fn handle_remove_liquidity<'info>(
+    ctx: Context<'_, '_, 'info, 'info, RemoveLiquidity<'info>>,
+    shares: u64,
+) -> Result<()> {
+    require!(shares > 0, PerpsError::ZeroAmount);
+
+    let clock = Clock::get()?;
+    let nav = load_pool_nav(
+        &ctx.accounts.liquidity_pool,
+        ctx.accounts.global_state.market_count,
+        ctx.remaining_accounts,
+        clock.unix_timestamp,
+    )?;
+    let share_supply = ctx.accounts.lp_mint.supply;
+    require!(shares <= share_supply, PerpsError::InsufficientBalance);
+    let amount = LiquidityPool::amount_for_shares(shares, nav, share_supply)?;
+    require!(amount > 0, PerpsError::ZeroAmount);
+    require!(
+        amount <= ctx.accounts.liquidity_pool.balance,
+        PerpsError::InsufficientPoolLiquidity
+    );
+
+    // Burn the provider's shares
+    let cpi_accounts = Burn {
+        mint: ctx.accounts.lp_mint.to_account_info(),
+        from: ctx.accounts.provider_share_ata.to_account_info(),
+        authority: ctx.accounts.provider.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+    token::burn(cpi_ctx, shares)?;
+
+    // Transfer USDC from treasury to provider (PDA signer)
+    let seeds = &[GLOBAL_STATE_SEED, &[ctx.accounts.global_state.bump]];
+    let signer_seeds = &[&seeds[..]];
+
+    let cpi_accounts = Transfer {
+        from: ctx.accounts.treasury.to_account_info(),
+        to: ctx.accounts.provider_ata.to_account_info(),
+        authority: ctx.accounts.global_state.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(
+        ctx.accounts.token_program.to_account_info(),
+        cpi_accounts,
+        signer_seeds,
+    );
+    token::transfer(cpi_ctx, amount)?;
+
+    let liquidity_pool = &mut ctx.accounts.liquidity_pool;
+    liquidity_pool.balance -= amount;
+
+    emit!(LiquidityChangedEvent {
+        provider: ctx.accounts.provider.key(),
+        amount: -(amount as i64),
+        shares: -(shares as i64),
+        nav,
+        share_supply,
+        timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct RemoveLiquidity<'info> {
+    pub provider: Signer<'info>,
+
+    #[account(
+        mut,
+        token::mint = global_state.usdc_mint,
+        token::authority = provider,
+    )]
+    pub provider_ata: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        token::mint = lp_mint,
+        token::authority = provider,
+    )]
+    pub provider_share_ata: Account<'info, TokenAccount>,
+
+    #[account(
+        seeds = [GLOBAL_STATE_SEED],
+        bump = global_state.bump,
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(
+        mut,
+        seeds = [LIQUIDITY_POOL_SEED],
+        bump = liquidity_pool.bump,
+        has_one = lp_mint,
+    )]
+    pub liquidity_pool: Account<'info, LiquidityPool>,
+
+    #[account(mut)]
+    pub lp_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED],
+        bump,
+        token::mint = global_state.usdc_mint,
+        token::authority = global_state,
+    )]
+    pub treasury: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+}
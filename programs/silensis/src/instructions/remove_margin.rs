@@ -0,0 +1,113 @@
+use anchor_lang::prelude::*;
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::math::{calculate_accrued_funding, calculate_margin_ratio, calculate_notional, calculate_pnl};
+use crate::oracle::read_oracle_price;
+use crate::state::{Market, Position, UserVault};
+
+pub fn handle_remove_margin(ctx: Context<RemoveMargin>, amount: u64) -> Result<()> {
+    require!(amount > 0, PerpsError::ZeroAmount);
+
+    let position = &ctx.accounts.position;
+    require!(amount < position.margin, PerpsError::InsufficientMargin);
+
+    // Get current price from oracle
+    let clock = Clock::get()?;
+    let current_price = read_oracle_price(
+        &ctx.accounts.market,
+        &ctx.accounts.price_feed,
+        clock.unix_timestamp,
+    )?;
+
+    // Bring the funding index up to date before settling against it
+    ctx.accounts.market.accrue_funding(clock.unix_timestamp)?;
+
+    // Calculate PnL net of funding accrued since open
+    let price_pnl = calculate_pnl(
+        position.direction,
+        position.size,
+        position.entry_price,
+        current_price,
+    )?;
+
+    let market = &ctx.accounts.market;
+    let funding_payment = calculate_accrued_funding(
+        position.size,
+        position.entry_price,
+        market.cumulative_funding_rate(position.direction),
+        position.cumulative_funding,
+    )?;
+
+    let pnl = price_pnl
+        .checked_sub(funding_payment)
+        .ok_or(PerpsError::MathOverflow)?;
+
+    // Post-removal ratio must stay above initial margin. Unrealized profit
+    // does not back removed margin; unrealized losses count against it.
+    let new_margin = position.margin - amount;
+    let margin_ratio = calculate_margin_ratio(
+        new_margin,
+        pnl.min(0),
+        position.size,
+        current_price,
+    )?;
+    require!(
+        margin_ratio >= market.initial_margin_bps(),
+        PerpsError::InsufficientMargin
+    );
+
+    // Unlock the removed margin in vault
+    let vault = &mut ctx.accounts.user_vault;
+    vault.locked_margin = vault
+        .locked_margin
+        .checked_sub(amount)
+        .ok_or(PerpsError::MathOverflow)?;
+
+    let position = &mut ctx.accounts.position;
+    position.update_trailing_price(current_price);
+    position.margin = new_margin;
+    position.leverage = calculate_notional(position.size, position.entry_price)?
+        .checked_div(new_margin)
+        .ok_or(PerpsError::MathOverflow)?;
+
+    msg!(
+        "Removed {} margin from position {}. Margin: {}, Margin ratio: {}",
+        amount,
+        position.position_id,
+        new_margin,
+        margin_ratio
+    );
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct RemoveMargin<'info> {
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [USER_VAULT_SEED, user.key().as_ref()],
+        bump = user_vault.bump,
+        constraint = user_vault.owner == user.key() @ PerpsError::Unauthorized,
+    )]
+    pub user_vault: Account<'info, UserVault>,
+
+    #[account(
+        mut,
+        constraint = position.owner == user.key() @ PerpsError::Unauthorized,
+        constraint = position.is_open @ PerpsError::PositionNotOpen,
+    )]
+    pub position: Account<'info, Position>,
+
+    #[account(
+        mut,
+        seeds = [MARKET_SEED, position.market_index.to_le_bytes().as_ref()],
+        bump = market.bump,
+        has_one = price_feed @ PerpsError::InvalidOracle,
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: must be the market's configured oracle; parsed by `read_oracle_price`
+    pub price_feed: UncheckedAccount<'info>,
+}
@@ -0,0 +1,70 @@
+use anchor_lang::prelude::*;
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::events::VammRepeggedEvent;
+use crate::oracle::read_oracle_price;
+use crate::state::{LiquidityPool, Market, PricingMode};
+
+/// Move a vAMM market's peg toward the oracle price. Traders' gain or loss
+/// on their net position settles against the liquidity pool when they
+/// close, so the move stops where it would exceed the market's repeg budget
+/// or the pool balance.
+pub fn handle_repeg_vamm(ctx: Context<RepegVamm>) -> Result<()> {
+    let market = &ctx.accounts.market;
+    require!(
+        market.pricing_mode == PricingMode::Vamm,
+        PerpsError::PricingModeMismatch
+    );
+
+    let clock = Clock::get()?;
+    let oracle_price = read_oracle_price(market, &ctx.accounts.price_feed, clock.unix_timestamp)?;
+
+    let market = &mut ctx.accounts.market;
+    let old_price = market.vamm_price()?;
+    let max_cost = market.repeg_budget.min(ctx.accounts.liquidity_pool.balance);
+    let cost = market.repeg(oracle_price, max_cost)?;
+    let new_price = market.vamm_price()?;
+
+    emit!(VammRepeggedEvent {
+        market_index: market.market_index,
+        oracle_price,
+        old_price,
+        new_price,
+        peg_multiplier: market.peg_multiplier,
+        cost,
+        timestamp: clock.unix_timestamp,
+    });
+
+    msg!(
+        "Market {} repegged. Oracle: {}, Price: {} -> {}, Cost: {}",
+        market.market_index,
+        oracle_price,
+        old_price,
+        new_price,
+        cost
+    );
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct RepegVamm<'info> {
+    pub caller: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [MARKET_SEED, market.market_index.to_le_bytes().as_ref()],
+        bump = market.bump,
+        has_one = price_feed @ PerpsError::InvalidOracle,
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [LIQUIDITY_POOL_SEED],
+        bump = liquidity_pool.bump,
+    )]
+    pub liquidity_pool: Account<'info, LiquidityPool>,
+
+    /// CHECK: must be the market's configured oracle; parsed by `read_oracle_price`
+    pub price_feed: UncheckedAccount<'info>,
+}
@@ -0,0 +1,26 @@
+use anchor_lang::prelude::*;
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::state::GlobalState;
+
+pub fn handle_set_guardian(ctx: Context<SetGuardian>, guardian: Pubkey) -> Result<()> {
+    let global = &mut ctx.accounts.global_state;
+    global.guardian = guardian;
+
+    msg!("Guardian set to {}", guardian);
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct SetGuardian<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATE_SEED],
+        bump = global_state.bump,
+        has_one = authority @ PerpsError::Unauthorized,
+    )]
+    pub global_state: Account<'info, GlobalState>,
+}
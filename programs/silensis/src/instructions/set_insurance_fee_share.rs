@@ -0,0 +1,37 @@
+use anchor_lang::prelude::*;
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::state::{GlobalState, InsuranceFund};
+
+pub fn handle_set_insurance_fee_share(
+    ctx: Context<SetInsuranceFeeShare>,
+    fee_share_bps: u64,
+) -> Result<()> {
+    require!(fee_share_bps <= BPS_PRECISION, PerpsError::InvalidParameter);
+
+    let insurance_fund = &mut ctx.accounts.insurance_fund;
+    insurance_fund.fee_share_bps = fee_share_bps;
+
+    msg!("Insurance fund fee share set to {} bps", fee_share_bps);
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct SetInsuranceFeeShare<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [GLOBAL_STATE_SEED],
+        bump = global_state.bump,
+        has_one = authority @ PerpsError::Unauthorized,
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(
+        mut,
+        seeds = [INSURANCE_FUND_SEED],
+        bump = insurance_fund.bump,
+    )]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+}
@@ -0,0 +1,34 @@
+use anchor_lang::prelude::*;
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::state::{GlobalState, LiquidityPool};
+
+pub fn handle_set_lp_fee_share(ctx: Context<SetLpFeeShare>, fee_share_bps: u64) -> Result<()> {
+    require!(fee_share_bps <= BPS_PRECISION, PerpsError::InvalidParameter);
+
+    let liquidity_pool = &mut ctx.accounts.liquidity_pool;
+    liquidity_pool.fee_share_bps = fee_share_bps;
+
+    msg!("Liquidity pool fee share set to {} bps", fee_share_bps);
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct SetLpFeeShare<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [GLOBAL_STATE_SEED],
+        bump = global_state.bump,
+        has_one = authority @ PerpsError::Unauthorized,
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(
+        mut,
+        seeds = [LIQUIDITY_POOL_SEED],
+        bump = liquidity_pool.bump,
+    )]
+    pub liquidity_pool: Account<'info, LiquidityPool>,
+}
@@ -0,0 +1,35 @@
+use anchor_lang::prelude::*;
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::state::{MarginMode, UserVault};
+
+pub fn handle_set_margin_mode(ctx: Context<SetMarginMode>, margin_mode: MarginMode) -> Result<()> {
+    let vault = &mut ctx.accounts.user_vault;
+    require!(vault.open_positions == 0, PerpsError::OpenPositionsExist);
+
+    vault.margin_mode = margin_mode;
+
+    msg!(
+        "Vault {} margin mode set to {}",
+        vault.owner,
+        match margin_mode {
+            MarginMode::Isolated => "isolated",
+            MarginMode::Cross => "cross",
+        }
+    );
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct SetMarginMode<'info> {
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [USER_VAULT_SEED, user.key().as_ref()],
+        bump = user_vault.bump,
+        constraint = user_vault.owner == user.key() @ PerpsError::Unauthorized,
+    )]
+    pub user_vault: Account<'info, UserVault>,
+}
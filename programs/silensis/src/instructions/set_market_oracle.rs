@@ -0,0 +1,59 @@
+use anchor_lang::prelude::*;
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::oracle::PythPrice;
+use crate::state::{GlobalState, Market, OracleSource};
+
+pub fn handle_set_market_oracle(
+    ctx: Context<SetMarketOracle>,
+    oracle_source: OracleSource,
+) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+    let oracle = &ctx.accounts.oracle;
+
+    Market::validate_risk_price_source(oracle_source, market.risk_price_source)?;
+
+    match oracle_source {
+        OracleSource::Internal => {
+            // Only the market's own PriceFeed can be used as an internal oracle
+            let (price_feed, _) = Pubkey::find_program_address(
+                &[PRICE_FEED_SEED, market.market_index.to_le_bytes().as_ref()],
+                &crate::ID,
+            );
+            require_keys_eq!(oracle.key(), price_feed, PerpsError::InvalidOracle);
+        }
+        OracleSource::Pyth => {
+            require_keys_eq!(*oracle.owner, PYTH_PROGRAM_ID, PerpsError::InvalidOracle);
+            PythPrice::parse(&oracle.try_borrow_data()?)?;
+        }
+    }
+
+    market.oracle_source = oracle_source;
+    market.price_feed = oracle.key();
+
+    msg!("Market {} oracle set to {}", market.market_index, market.price_feed);
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct SetMarketOracle<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [GLOBAL_STATE_SEED],
+        bump = global_state.bump,
+        has_one = authority @ PerpsError::Unauthorized,
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(
+        mut,
+        seeds = [MARKET_SEED, market.market_index.to_le_bytes().as_ref()],
+        bump = market.bump,
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: validated against the requested oracle source in the handler
+    pub oracle: UncheckedAccount<'info>,
+}
@@ -0,0 +1,70 @@
+use anchor_lang::prelude::*;
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::oracle::read_oracle_price;
+use crate::state::{GlobalState, Market, PricingMode};
+
+/// Switch a market between oracle and vAMM execution prices. A vAMM starts
+/// with equal base and quote reserves of `base_reserve`, pegged to the
+/// current oracle price; deeper reserves mean less price impact per trade.
+pub fn handle_set_market_pricing(
+    ctx: Context<SetMarketPricing>,
+    pricing_mode: PricingMode,
+    base_reserve: u64,
+) -> Result<()> {
+    let market = &ctx.accounts.market;
+    // Open positions were filled against the current price source
+    require!(!market.has_open_interest(), PerpsError::MarketHasOpenInterest);
+
+    let (base_reserve, peg_multiplier) = match pricing_mode {
+        PricingMode::Oracle => (0, 0),
+        PricingMode::Vamm => {
+            require!(base_reserve > 0, PerpsError::InvalidParameter);
+            let oracle_price = read_oracle_price(
+                market,
+                &ctx.accounts.price_feed,
+                Clock::get()?.unix_timestamp,
+            )?;
+            (base_reserve as u128, oracle_price as u128)
+        }
+    };
+
+    let market = &mut ctx.accounts.market;
+    market.pricing_mode = pricing_mode;
+    market.base_reserve = base_reserve;
+    market.quote_reserve = base_reserve;
+    market.peg_multiplier = peg_multiplier;
+    market.vamm_net_base = 0;
+
+    msg!(
+        "Market {} pricing set. Base reserve: {}, Peg: {}",
+        market.market_index,
+        base_reserve,
+        peg_multiplier
+    );
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct SetMarketPricing<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [GLOBAL_STATE_SEED],
+        bump = global_state.bump,
+        has_one = authority @ PerpsError::Unauthorized,
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(
+        mut,
+        seeds = [MARKET_SEED, market.market_index.to_le_bytes().as_ref()],
+        bump = market.bump,
+        has_one = price_feed @ PerpsError::InvalidOracle,
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: must be the market's configured oracle; parsed by `read_oracle_price`
+    pub price_feed: UncheckedAccount<'info>,
+}
@@ -0,0 +1,31 @@
+use anchor_lang::prelude::*;
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::state::GlobalState;
+
+pub fn handle_set_max_user_notional(
+    ctx: Context<SetMaxUserNotional>,
+    max_user_notional: u64,
+) -> Result<()> {
+    require!(max_user_notional > 0, PerpsError::InvalidParameter);
+
+    let global = &mut ctx.accounts.global_state;
+    global.max_user_notional = max_user_notional;
+
+    msg!("Max open notional per user set to {}", max_user_notional);
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct SetMaxUserNotional<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATE_SEED],
+        bump = global_state.bump,
+        has_one = authority @ PerpsError::Unauthorized,
+    )]
+    pub global_state: Account<'info, GlobalState>,
+}
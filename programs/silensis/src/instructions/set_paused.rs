@@ -0,0 +1,33 @@
+use anchor_lang::prelude::*;
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::state::GlobalState;
+
+pub fn handle_set_paused(ctx: Context<SetPaused>, paused: bool) -> Result<()> {
+    let global = &mut ctx.accounts.global_state;
+    let caller = ctx.accounts.caller.key();
+
+    // The guardian can only pause; unpausing is reserved for the authority
+    require!(
+        caller == global.authority || (paused && caller == global.guardian),
+        PerpsError::Unauthorized
+    );
+
+    global.is_paused = paused;
+
+    msg!("Protocol paused: {}", paused);
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct SetPaused<'info> {
+    pub caller: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATE_SEED],
+        bump = global_state.bump,
+    )]
+    pub global_state: Account<'info, GlobalState>,
+}
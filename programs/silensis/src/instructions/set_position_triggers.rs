@@ -0,0 +1,73 @@
+use anchor_lang::prelude::*;
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::oracle::read_oracle_price;
+use crate::state::{Market, Position};
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct PositionTriggersParams {
+    pub stop_loss_price: u64,   // 0 disables
+    pub take_profit_price: u64, // 0 disables
+    pub trailing_stop_bps: u64, // 0 disables
+}
+
+/// Set a position's stop-loss, take-profit and trailing stop, replacing any
+/// previous ones. A trailing stop starts from the current oracle price.
+pub fn handle_set_position_triggers(
+    ctx: Context<SetPositionTriggers>,
+    params: PositionTriggersParams,
+) -> Result<()> {
+    require!(
+        params.trailing_stop_bps < BPS_PRECISION,
+        PerpsError::InvalidParameter
+    );
+
+    let clock = Clock::get()?;
+    let current_price = read_oracle_price(
+        &ctx.accounts.market,
+        &ctx.accounts.price_feed,
+        clock.unix_timestamp,
+    )?;
+
+    let position = &mut ctx.accounts.position;
+    position.stop_loss_price = params.stop_loss_price;
+    position.take_profit_price = params.take_profit_price;
+    position.trailing_stop_bps = params.trailing_stop_bps;
+    position.trailing_price = if params.trailing_stop_bps > 0 {
+        current_price
+    } else {
+        0
+    };
+
+    msg!(
+        "Position {} triggers set. SL: {}, TP: {}, Trailing: {} bps",
+        position.position_id,
+        position.stop_loss_price,
+        position.take_profit_price,
+        position.trailing_stop_bps
+    );
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct SetPositionTriggers<'info> {
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = position.owner == user.key() @ PerpsError::Unauthorized,
+        constraint = position.is_open @ PerpsError::PositionNotOpen,
+    )]
+    pub position: Account<'info, Position>,
+
+    #[account(
+        seeds = [MARKET_SEED, position.market_index.to_le_bytes().as_ref()],
+        bump = market.bump,
+        has_one = price_feed @ PerpsError::InvalidOracle,
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: must be the market's configured oracle; parsed by `read_oracle_price`
+    pub price_feed: UncheckedAccount<'info>,
+}
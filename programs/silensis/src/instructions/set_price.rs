@@ -1,14 +1,17 @@
 use anchor_lang::prelude::*;
 use crate::constants::*;
 use crate::errors::PerpsError;
-use crate::state::{GlobalState, PriceFeed};
+use crate::state::{Market, PriceFeed};
 
 pub fn handle_set_price(ctx: Context<SetPrice>, price: u64) -> Result<()> {
     require!(price > 0, PerpsError::InvalidParameter);
 
     let price_feed = &mut ctx.accounts.price_feed;
-    price_feed.price = price;
-    price_feed.timestamp = Clock::get()?.unix_timestamp;
+    price_feed.submit(
+        ctx.accounts.publisher.key(),
+        price,
+        Clock::get()?.unix_timestamp,
+    )?;
 
     Ok(())
 }
@@ -16,19 +19,18 @@ pub fn handle_set_price(ctx: Context<SetPrice>, price: u64) -> Result<()> {
 #[derive(Accounts)]
 pub struct SetPrice<'info> {
     #[account(mut)]
-    pub authority: Signer<'info>,
+    pub publisher: Signer<'info>,
 
     #[account(
-        seeds = [GLOBAL_STATE_SEED],
-        bump = global_state.bump,
-        has_one = authority @ PerpsError::Unauthorized,
+        seeds = [MARKET_SEED, market.market_index.to_le_bytes().as_ref()],
+        bump = market.bump,
     )]
-    pub global_state: Account<'info, GlobalState>,
+    pub market: Account<'info, Market>,
 
     #[account(
         mut,
-        seeds = [PRICE_FEED_SEED],
+        seeds = [PRICE_FEED_SEED, market.market_index.to_le_bytes().as_ref()],
         bump = price_feed.bump,
     )]
-    pub price_feed: Account<'info, PriceFeed>,
+    pub price_feed: Box<Account<'info, PriceFeed>>,
 }
@@ -0,0 +1,47 @@
+use anchor_lang::prelude::*;
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::state::{GlobalState, Market, PriceFeed};
+
+pub fn handle_set_price_publishers(
+    ctx: Context<SetPricePublishers>,
+    publishers: Vec<Pubkey>,
+    min_publishers: u8,
+) -> Result<()> {
+    let price_feed = &mut ctx.accounts.price_feed;
+    price_feed.set_publishers(&publishers, min_publishers)?;
+
+    msg!(
+        "Market {} price publishers set: {} with quorum {}",
+        ctx.accounts.market.market_index,
+        publishers.len(),
+        min_publishers
+    );
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct SetPricePublishers<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [GLOBAL_STATE_SEED],
+        bump = global_state.bump,
+        has_one = authority @ PerpsError::Unauthorized,
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(
+        seeds = [MARKET_SEED, market.market_index.to_le_bytes().as_ref()],
+        bump = market.bump,
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [PRICE_FEED_SEED, market.market_index.to_le_bytes().as_ref()],
+        bump = price_feed.bump,
+    )]
+    pub price_feed: Box<Account<'info, PriceFeed>>,
+}
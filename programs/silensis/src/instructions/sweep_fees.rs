@@ -0,0 +1,69 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token::{self, Token, TokenAccount, Transfer};
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::state::GlobalState;
+
+pub fn handle_sweep_fees(ctx: Context<SweepFees>, amount: u64) -> Result<()> {
+    require!(amount > 0, PerpsError::ZeroAmount);
+    require!(
+        amount <= ctx.accounts.global_state.protocol_fee_balance,
+        PerpsError::InsufficientBalance
+    );
+
+    // Transfer USDC from treasury to the destination (PDA signer)
+    let seeds = &[GLOBAL_STATE_SEED, &[ctx.accounts.global_state.bump]];
+    let signer_seeds = &[&seeds[..]];
+
+    let cpi_accounts = Transfer {
+        from: ctx.accounts.treasury.to_account_info(),
+        to: ctx.accounts.destination.to_account_info(),
+        authority: ctx.accounts.global_state.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(
+        ctx.accounts.token_program.to_account_info(),
+        cpi_accounts,
+        signer_seeds,
+    );
+    token::transfer(cpi_ctx, amount)?;
+
+    let global = &mut ctx.accounts.global_state;
+    global.protocol_fee_balance = global
+        .protocol_fee_balance
+        .checked_sub(amount)
+        .ok_or(PerpsError::MathOverflow)?;
+
+    msg!("Swept {} in protocol fees", amount);
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct SweepFees<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATE_SEED],
+        bump = global_state.bump,
+        has_one = authority @ PerpsError::Unauthorized,
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(
+        mut,
+        seeds = [TREASURY_SEED],
+        bump,
+        token::mint = global_state.usdc_mint,
+        token::authority = global_state,
+    )]
+    pub treasury: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        token::mint = global_state.usdc_mint,
+    )]
+    pub destination: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+}
@@ -0,0 +1,116 @@
+use anchor_lang::prelude::*;
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::state::{GlobalState, Market, RiskPriceSource};
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct UpdateMarketParams {
+    pub max_leverage: Option<u64>,
+    pub maintenance_margin_bps: Option<u64>,
+    pub liquidation_fee_bps: Option<u64>,
+    pub full_liquidation_margin_bps: Option<u64>,
+    pub taker_fee_bps: Option<u64>,
+    pub risk_price_source: Option<RiskPriceSource>,
+    pub twap_window: Option<i64>,
+    pub skew_scale: Option<u64>,
+    pub max_funding_rate_bps: Option<u64>,
+    pub max_open_interest: Option<u64>,
+    pub max_long_oi: Option<u64>,
+    pub max_short_oi: Option<u64>,
+    pub max_position_notional: Option<u64>,
+    pub repeg_budget: Option<u64>,
+}
+
+pub fn handle_update_market(
+    ctx: Context<UpdateMarket>,
+    params: UpdateMarketParams,
+) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+
+    // Funding up to now accrues under the old skew scale and rate cap
+    market.accrue_funding(Clock::get()?.unix_timestamp)?;
+
+    let max_leverage = params.max_leverage.unwrap_or(market.max_leverage);
+    let maintenance_margin_bps = params
+        .maintenance_margin_bps
+        .unwrap_or(market.maintenance_margin_bps);
+    let liquidation_fee_bps = params
+        .liquidation_fee_bps
+        .unwrap_or(market.liquidation_fee_bps);
+    let full_liquidation_margin_bps = params
+        .full_liquidation_margin_bps
+        .unwrap_or(market.full_liquidation_margin_bps);
+    let taker_fee_bps = params.taker_fee_bps.unwrap_or(market.taker_fee_bps);
+    let risk_price_source = params
+        .risk_price_source
+        .unwrap_or(market.risk_price_source);
+    let twap_window = params.twap_window.unwrap_or(market.twap_window);
+    let skew_scale = params.skew_scale.unwrap_or(market.skew_scale);
+    let max_funding_rate_bps = params
+        .max_funding_rate_bps
+        .unwrap_or(market.max_funding_rate_bps);
+    let max_open_interest = params.max_open_interest.unwrap_or(market.max_open_interest);
+    let max_long_oi = params.max_long_oi.unwrap_or(market.max_long_oi);
+    let max_short_oi = params.max_short_oi.unwrap_or(market.max_short_oi);
+    let max_position_notional = params
+        .max_position_notional
+        .unwrap_or(market.max_position_notional);
+
+    // Validate the resulting parameter set as a whole
+    Market::validate_risk_params(
+        max_leverage,
+        maintenance_margin_bps,
+        liquidation_fee_bps,
+        full_liquidation_margin_bps,
+    )?;
+    Market::validate_fee_params(taker_fee_bps)?;
+    Market::validate_risk_price_source(market.oracle_source, risk_price_source)?;
+    Market::validate_price_params(twap_window)?;
+    Market::validate_funding_params(skew_scale, max_funding_rate_bps)?;
+    Market::validate_oi_params(
+        max_open_interest,
+        max_long_oi,
+        max_short_oi,
+        max_position_notional,
+    )?;
+
+    market.max_leverage = max_leverage;
+    market.maintenance_margin_bps = maintenance_margin_bps;
+    market.liquidation_fee_bps = liquidation_fee_bps;
+    market.full_liquidation_margin_bps = full_liquidation_margin_bps;
+    market.taker_fee_bps = taker_fee_bps;
+    market.risk_price_source = risk_price_source;
+    market.twap_window = twap_window;
+    market.skew_scale = skew_scale;
+    market.max_funding_rate_bps = max_funding_rate_bps;
+    market.max_open_interest = max_open_interest;
+    market.max_long_oi = max_long_oi;
+    market.max_short_oi = max_short_oi;
+    market.max_position_notional = max_position_notional;
+    if let Some(repeg_budget) = params.repeg_budget {
+        market.repeg_budget = repeg_budget;
+    }
+
+    msg!("Market {} parameters updated", market.market_index);
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct UpdateMarket<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [GLOBAL_STATE_SEED],
+        bump = global_state.bump,
+        has_one = authority @ PerpsError::Unauthorized,
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    #[account(
+        mut,
+        seeds = [MARKET_SEED, market.market_index.to_le_bytes().as_ref()],
+        bump = market.bump,
+    )]
+    pub market: Account<'info, Market>,
+}
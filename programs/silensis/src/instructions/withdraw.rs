@@ -2,17 +2,32 @@ use anchor_lang::prelude::*;
 use anchor_spl::token::{self, Token, TokenAccount, Transfer};
 use crate::constants::*;
 use crate::errors::PerpsError;
+use crate::margin::AccountHealth;
+use crate::oracle::read_oracle_price;
 use crate::state::{GlobalState, UserVault};
 
-pub fn handle_withdraw(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
+/// Remaining accounts: a (position, market, price feed) triple for each of
+/// the user's open positions, so unrealized losses count against the withdrawal.
+pub fn handle_withdraw<'info>(
+    ctx: Context<'_, '_, 'info, 'info, Withdraw<'info>>,
+    amount: u64,
+) -> Result<()> {
     require!(amount > 0, PerpsError::ZeroAmount);
 
     let vault = &ctx.accounts.user_vault;
-    let available = vault
-        .deposited_amount
-        .checked_sub(vault.locked_margin)
-        .ok_or(PerpsError::MathOverflow)?;
-    require!(amount <= available, PerpsError::InsufficientBalance);
+    require!(amount <= vault.free_balance()?, PerpsError::InsufficientBalance);
+
+    // Equity after the withdrawal, including unrealized PnL, must still
+    // cover initial margin on every open position
+    if vault.open_positions > 0 {
+        let health = AccountHealth::load(
+            vault,
+            ctx.remaining_accounts,
+            Clock::get()?.unix_timestamp,
+            read_oracle_price,
+        )?;
+        health.require_initial_margin_after(amount)?;
+    }
 
     // Transfer USDC from treasury to user (PDA signer)
     let seeds = &[GLOBAL_STATE_SEED, &[ctx.accounts.global_state.bump]];
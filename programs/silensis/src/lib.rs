@@ -2,11 +2,17 @@ use anchor_lang::prelude::*;
 
 pub mod constants;
 pub mod errors;
+pub mod events;
 pub mod instructions;
+pub mod margin;
 pub mod math;
+pub mod oracle;
+pub mod pool;
+pub mod settlement;
 pub mod state;
 
 use instructions::*;
+use state::{MarginMode, OracleSource, PricingMode};
 
 declare_id!("AY4EDSxDQXhx5neK8ygEuZY1ogE8JkeTVjpUNSwhyJep");
 
@@ -18,6 +24,13 @@ pub mod silensis {
         instructions::initialize::handle_initialize(ctx)
     }
 
+    pub fn initialize_market(
+        ctx: Context<InitializeMarket>,
+        params: InitializeMarketParams,
+    ) -> Result<()> {
+        instructions::initialize_market::handle_initialize_market(ctx, params)
+    }
+
     pub fn set_price(ctx: Context<SetPrice>, price: u64) -> Result<()> {
         instructions::set_price::handle_set_price(ctx, price)
     }
@@ -26,26 +39,187 @@ pub mod silensis {
         instructions::deposit::handle_deposit(ctx, amount)
     }
 
-    pub fn withdraw(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
+    pub fn withdraw<'info>(
+        ctx: Context<'_, '_, 'info, 'info, Withdraw<'info>>,
+        amount: u64,
+    ) -> Result<()> {
         instructions::withdraw::handle_withdraw(ctx, amount)
     }
 
-    pub fn open_position(
-        ctx: Context<OpenPosition>,
+    pub fn open_position<'info>(
+        ctx: Context<'_, '_, 'info, 'info, OpenPosition<'info>>,
         params: OpenPositionParams,
     ) -> Result<()> {
         instructions::open_position::handle_open_position(ctx, params)
     }
 
-    pub fn close_position(ctx: Context<ClosePosition>) -> Result<()> {
-        instructions::close_position::handle_close_position(ctx)
+    pub fn close_position(
+        ctx: Context<ClosePosition>,
+        acceptable_price: Option<u64>,
+        expiry_timestamp: Option<i64>,
+    ) -> Result<()> {
+        instructions::close_position::handle_close_position(ctx, acceptable_price, expiry_timestamp)
+    }
+
+    pub fn decrease_position(
+        ctx: Context<DecreasePosition>,
+        params: DecreasePositionParams,
+    ) -> Result<()> {
+        instructions::decrease_position::handle_decrease_position(ctx, params)
+    }
+
+    pub fn increase_position<'info>(
+        ctx: Context<'_, '_, 'info, 'info, IncreasePosition<'info>>,
+        params: IncreasePositionParams,
+    ) -> Result<()> {
+        instructions::increase_position::handle_increase_position(ctx, params)
+    }
+
+    pub fn add_margin(ctx: Context<AddMargin>, amount: u64) -> Result<()> {
+        instructions::add_margin::handle_add_margin(ctx, amount)
+    }
+
+    pub fn remove_margin(ctx: Context<RemoveMargin>, amount: u64) -> Result<()> {
+        instructions::remove_margin::handle_remove_margin(ctx, amount)
+    }
+
+    pub fn set_margin_mode(ctx: Context<SetMarginMode>, margin_mode: MarginMode) -> Result<()> {
+        instructions::set_margin_mode::handle_set_margin_mode(ctx, margin_mode)
     }
 
     pub fn liquidate(ctx: Context<Liquidate>) -> Result<()> {
         instructions::liquidate::handle_liquidate(ctx)
     }
 
+    pub fn liquidate_account<'info>(
+        ctx: Context<'_, '_, 'info, 'info, LiquidateAccount<'info>>,
+    ) -> Result<()> {
+        instructions::liquidate_account::handle_liquidate_account(ctx)
+    }
+
     pub fn apply_funding(ctx: Context<ApplyFunding>) -> Result<()> {
         instructions::apply_funding::handle_apply_funding(ctx)
     }
+
+    pub fn update_market(
+        ctx: Context<UpdateMarket>,
+        params: UpdateMarketParams,
+    ) -> Result<()> {
+        instructions::update_market::handle_update_market(ctx, params)
+    }
+
+    pub fn set_paused(ctx: Context<SetPaused>, paused: bool) -> Result<()> {
+        instructions::set_paused::handle_set_paused(ctx, paused)
+    }
+
+    pub fn propose_authority(
+        ctx: Context<ProposeAuthority>,
+        new_authority: Pubkey,
+    ) -> Result<()> {
+        instructions::propose_authority::handle_propose_authority(ctx, new_authority)
+    }
+
+    pub fn accept_authority(ctx: Context<AcceptAuthority>) -> Result<()> {
+        instructions::accept_authority::handle_accept_authority(ctx)
+    }
+
+    pub fn set_guardian(ctx: Context<SetGuardian>, guardian: Pubkey) -> Result<()> {
+        instructions::set_guardian::handle_set_guardian(ctx, guardian)
+    }
+
+    pub fn set_price_publishers(
+        ctx: Context<SetPricePublishers>,
+        publishers: Vec<Pubkey>,
+        min_publishers: u8,
+    ) -> Result<()> {
+        instructions::set_price_publishers::handle_set_price_publishers(
+            ctx,
+            publishers,
+            min_publishers,
+        )
+    }
+
+    pub fn set_market_oracle(
+        ctx: Context<SetMarketOracle>,
+        oracle_source: OracleSource,
+    ) -> Result<()> {
+        instructions::set_market_oracle::handle_set_market_oracle(ctx, oracle_source)
+    }
+
+    pub fn sweep_fees(ctx: Context<SweepFees>, amount: u64) -> Result<()> {
+        instructions::sweep_fees::handle_sweep_fees(ctx, amount)
+    }
+
+    pub fn set_insurance_fee_share(
+        ctx: Context<SetInsuranceFeeShare>,
+        fee_share_bps: u64,
+    ) -> Result<()> {
+        instructions::set_insurance_fee_share::handle_set_insurance_fee_share(ctx, fee_share_bps)
+    }
+
+    pub fn set_max_user_notional(
+        ctx: Context<SetMaxUserNotional>,
+        max_user_notional: u64,
+    ) -> Result<()> {
+        instructions::set_max_user_notional::handle_set_max_user_notional(ctx, max_user_notional)
+    }
+
+    pub fn set_market_pricing(
+        ctx: Context<SetMarketPricing>,
+        pricing_mode: PricingMode,
+        base_reserve: u64,
+    ) -> Result<()> {
+        instructions::set_market_pricing::handle_set_market_pricing(ctx, pricing_mode, base_reserve)
+    }
+
+    pub fn repeg_vamm(ctx: Context<RepegVamm>) -> Result<()> {
+        instructions::repeg_vamm::handle_repeg_vamm(ctx)
+    }
+
+    pub fn add_liquidity<'info>(
+        ctx: Context<'_, '_, 'info, 'info, AddLiquidity<'info>>,
+        amount: u64,
+    ) -> Result<()> {
+        instructions::add_liquidity::handle_add_liquidity(ctx, amount)
+    }
+
+    pub fn remove_liquidity<'info>(
+        ctx: Context<'_, '_, 'info, 'info, RemoveLiquidity<'info>>,
+        shares: u64,
+    ) -> Result<()> {
+        instructions::remove_liquidity::handle_remove_liquidity(ctx, shares)
+    }
+
+    pub fn set_lp_fee_share(ctx: Context<SetLpFeeShare>, fee_share_bps: u64) -> Result<()> {
+        instructions::set_lp_fee_share::handle_set_lp_fee_share(ctx, fee_share_bps)
+    }
+
+    pub fn place_order(ctx: Context<PlaceOrder>, params: PlaceOrderParams) -> Result<()> {
+        instructions::place_order::handle_place_order(ctx, params)
+    }
+
+    pub fn cancel_order(ctx: Context<CancelOrder>) -> Result<()> {
+        instructions::cancel_order::handle_cancel_order(ctx)
+    }
+
+    pub fn close_expired_order(ctx: Context<CloseExpiredOrder>) -> Result<()> {
+        instructions::close_expired_order::handle_close_expired_order(ctx)
+    }
+
+    pub fn execute_order<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ExecuteOrder<'info>>,
+    ) -> Result<()> {
+        instructions::execute_order::handle_execute_order(ctx)
+    }
+
+    pub fn set_position_triggers(
+        ctx: Context<SetPositionTriggers>,
+        params: PositionTriggersParams,
+    ) -> Result<()> {
+        instructions::set_position_triggers::handle_set_position_triggers(ctx, params)
+    }
+
+    pub fn execute_trigger(ctx: Context<ExecuteTrigger>) -> Result<()> {
+        instructions::execute_trigger::handle_execute_trigger(ctx)
+    }
 }
@@ -0,0 +1,171 @@
+use anchor_lang::prelude::*;
+use crate::constants::BPS_PRECISION;
+use crate::errors::PerpsError;
+use crate::math::{calculate_accrued_funding, calculate_notional, calculate_pnl};
+use crate::state::{MarginMode, Market, Position, UserVault};
+
+/// An open position valued at its market's current oracle price.
+pub struct PositionValuation<'info> {
+    pub position: Account<'info, Position>,
+    pub market: usize, // index into AccountHealth::markets
+    pub current_price: u64,
+    pub pnl: i64, // unrealized PnL net of accrued funding
+}
+
+/// Health of a vault across all of its open positions.
+pub struct AccountHealth<'info> {
+    pub positions: Vec<PositionValuation<'info>>,
+    pub markets: Vec<Account<'info, Market>>, // distinct markets traded
+    pub equity: i64,                          // deposit plus unrealized PnL
+    pub maintenance_margin: u64,              // summed at current prices
+    pub initial_margin: u64,                  // summed at current prices
+}
+
+impl<'info> AccountHealth<'info> {
+    /// Value every open position of `vault` at the price given by
+    /// `read_price`. `accounts` must hold a (position, market, price feed)
+    /// triple for each of them, and nothing else.
+    pub fn load(
+        vault: &UserVault,
+        accounts: &'info [AccountInfo<'info>],
+        now: i64,
+        read_price: fn(&Market, &AccountInfo, i64) -> Result<u64>,
+    ) -> Result<Self> {
+        require!(
+            accounts.len() == vault.open_positions as usize * 3,
+            PerpsError::InvalidRemainingAccounts
+        );
+
+        let mut positions: Vec<PositionValuation<'info>> = Vec::with_capacity(accounts.len() / 3);
+        let mut markets: Vec<Account<'info, Market>> = Vec::new();
+        let mut equity = vault.deposited_amount as i64;
+        let mut maintenance_margin: u64 = 0;
+        let mut initial_margin: u64 = 0;
+
+        for triple in accounts.chunks(3) {
+            let position = Account::<Position>::try_from(&triple[0])?;
+            require!(position.owner == vault.owner, PerpsError::Unauthorized);
+            require!(position.is_open, PerpsError::PositionNotOpen);
+            require!(
+                positions.iter().all(|p| p.position.key() != position.key()),
+                PerpsError::InvalidRemainingAccounts
+            );
+
+            // Positions on the same market share one market account
+            let market_idx = match markets.iter().position(|m| m.key() == triple[1].key()) {
+                Some(idx) => idx,
+                None => {
+                    let mut market = Account::<Market>::try_from(&triple[1])?;
+                    market.accrue_funding(now)?;
+                    markets.push(market);
+                    markets.len() - 1
+                }
+            };
+            let market = &markets[market_idx];
+            require!(
+                market.market_index == position.market_index,
+                PerpsError::InvalidRemainingAccounts
+            );
+
+            let current_price = read_price(market, &triple[2], now)?;
+
+            let pnl = calculate_pnl(
+                position.direction,
+                position.size,
+                position.entry_price,
+                current_price,
+            )?
+            .checked_sub(calculate_accrued_funding(
+                position.size,
+                position.entry_price,
+                market.cumulative_funding_rate(position.direction),
+                position.cumulative_funding,
+            )?)
+            .ok_or(PerpsError::MathOverflow)?;
+
+            let notional = calculate_notional(position.size, current_price)?;
+            let requirement = |margin_bps: u64| -> Result<u64> {
+                Ok((notional as u128)
+                    .checked_mul(margin_bps as u128)
+                    .ok_or(PerpsError::MathOverflow)?
+                    .checked_div(BPS_PRECISION as u128)
+                    .ok_or(PerpsError::MathOverflow)? as u64)
+            };
+
+            equity = equity.checked_add(pnl).ok_or(PerpsError::MathOverflow)?;
+            maintenance_margin = maintenance_margin
+                .checked_add(requirement(market.maintenance_margin_bps)?)
+                .ok_or(PerpsError::MathOverflow)?;
+            initial_margin = initial_margin
+                .checked_add(requirement(market.initial_margin_bps())?)
+                .ok_or(PerpsError::MathOverflow)?;
+
+            positions.push(PositionValuation {
+                position,
+                market: market_idx,
+                current_price,
+                pnl,
+            });
+        }
+
+        Ok(Self {
+            positions,
+            markets,
+            equity,
+            maintenance_margin,
+            initial_margin,
+        })
+    }
+
+    /// Fail if taking `amount` out of the vault would leave equity below the
+    /// initial margin of all open positions.
+    pub fn require_initial_margin_after(&self, amount: u64) -> Result<()> {
+        let equity = self
+            .equity
+            .checked_sub(amount as i64)
+            .ok_or(PerpsError::MathOverflow)?;
+        require!(
+            equity >= self.initial_margin as i64,
+            PerpsError::InsufficientMargin
+        );
+        Ok(())
+    }
+
+    /// Equity left over once initial margin on every open position and any
+    /// margin escrowed by `vault`'s open orders is set aside.
+    pub fn initial_margin_headroom(&self, vault: &UserVault) -> Result<i64> {
+        let position_margin = self
+            .positions
+            .iter()
+            .try_fold(0u64, |sum, p| sum.checked_add(p.position.margin))
+            .ok_or(PerpsError::MathOverflow)?;
+        let escrowed = vault.locked_margin.saturating_sub(position_margin);
+
+        self.equity
+            .checked_sub(self.initial_margin as i64)
+            .ok_or(PerpsError::MathOverflow)?
+            .checked_sub(escrowed as i64)
+            .ok_or(PerpsError::MathOverflow.into())
+    }
+
+    /// Whether account equity has fallen below total maintenance margin.
+    pub fn is_liquidatable(&self) -> bool {
+        self.equity < self.maintenance_margin as i64
+    }
+}
+
+/// Initial margin headroom of `vault` if it is in cross-margin mode, where
+/// equity backs new exposure. `None` for an isolated vault, which backs a new
+/// position from its free balance and ignores `accounts`.
+pub fn cross_margin_headroom<'info>(
+    vault: &UserVault,
+    accounts: &'info [AccountInfo<'info>],
+    now: i64,
+    read_price: fn(&Market, &AccountInfo, i64) -> Result<u64>,
+) -> Result<Option<i64>> {
+    if vault.margin_mode != MarginMode::Cross {
+        return Ok(None);
+    }
+    let health = AccountHealth::load(vault, accounts, now, read_price)?;
+    Ok(Some(health.initial_margin_headroom(vault)?))
+}
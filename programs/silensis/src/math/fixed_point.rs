@@ -47,15 +47,9 @@ pub fn calculate_margin_ratio(
     size: u64,
     current_price: u64,
 ) -> Result<u64> {
-    let effective_margin = if pnl >= 0 {
-        (margin as i128)
-            .checked_add(pnl as i128)
-            .ok_or(PerpsError::MathOverflow)?
-    } else {
-        (margin as i128)
-            .checked_add(pnl as i128)
-            .ok_or(PerpsError::MathOverflow)?
-    };
+    let effective_margin = (margin as i128)
+        .checked_add(pnl as i128)
+        .ok_or(PerpsError::MathOverflow)?;
 
     if effective_margin <= 0 {
         return Ok(0);
@@ -80,6 +74,42 @@ pub fn calculate_margin_ratio(
     Ok(ratio as u64)
 }
 
+/// Calculate the notional value of a position in USDC (6 decimals).
+/// notional = size * price / SIZE_PRECISION
+pub fn calculate_notional(size: u64, price: u64) -> Result<u64> {
+    let notional = (size as u128)
+        .checked_mul(price as u128)
+        .ok_or(PerpsError::MathOverflow)?
+        .checked_div(SIZE_PRECISION as u128)
+        .ok_or(PerpsError::MathOverflow)?;
+
+    u64::try_from(notional).map_err(|_| PerpsError::MathOverflow.into())
+}
+
+/// Calculate the position size worth `notional` USDC at `price`.
+/// size = notional * SIZE_PRECISION / price
+pub fn calculate_size(notional: u64, price: u64) -> Result<u64> {
+    let size = (notional as u128)
+        .checked_mul(SIZE_PRECISION as u128)
+        .ok_or(PerpsError::MathOverflow)?
+        .checked_div(price as u128)
+        .ok_or(PerpsError::MathOverflow)?;
+
+    u64::try_from(size).map_err(|_| PerpsError::MathOverflow.into())
+}
+
+/// Calculate a fee in basis points of an amount.
+/// fee = amount * fee_bps / BPS_PRECISION
+pub fn calculate_fee(amount: u64, fee_bps: u64) -> Result<u64> {
+    let fee = (amount as u128)
+        .checked_mul(fee_bps as u128)
+        .ok_or(PerpsError::MathOverflow)?
+        .checked_div(BPS_PRECISION as u128)
+        .ok_or(PerpsError::MathOverflow)?;
+
+    Ok(fee as u64)
+}
+
 /// Calculate the liquidation price for a position.
 /// For longs: liq_price = entry_price - (margin * SIZE_PRECISION / size)
 /// For shorts: liq_price = entry_price + (margin * SIZE_PRECISION / size)
@@ -98,9 +128,7 @@ pub fn calculate_liquidation_price(
 
     match direction {
         Direction::Long => {
-            let liq_price = (entry_price as u128)
-                .checked_sub(margin_per_unit)
-                .unwrap_or(0);
+            let liq_price = (entry_price as u128).saturating_sub(margin_per_unit);
             Ok(liq_price as u64)
         }
         Direction::Short => {
@@ -112,61 +140,198 @@ pub fn calculate_liquidation_price(
     }
 }
 
-/// Calculate the funding rate based on open interest imbalance.
-/// Positive rate means longs pay shorts.
-/// rate = (long_oi - short_oi) * FUNDING_RATE_PRECISION / (long_oi + short_oi)
-pub fn calculate_funding_rate(
+/// Calculate how much size to liquidate to bring a position back to a target
+/// margin ratio, given that the liquidation fee is charged on the margin of
+/// the liquidated slice and the remaining position keeps all remaining equity.
+/// Solving (equity - fraction * margin * fee) / (notional * (1 - fraction)) >= target:
+/// fraction = (target * notional - equity) / (target * notional - margin * fee)
+/// Returns the full size when no partial liquidation can restore the target.
+pub fn calculate_partial_liquidation_size(
+    size: u64,
+    margin: u64,
+    effective_margin: i64,
+    current_price: u64,
+    target_margin_bps: u64,
+    liquidation_fee_bps: u64,
+) -> Result<u64> {
+    if effective_margin <= 0 {
+        return Ok(size);
+    }
+
+    let notional = calculate_notional(size, current_price)? as i128;
+    let target = (target_margin_bps as i128)
+        .checked_mul(notional)
+        .ok_or(PerpsError::MathOverflow)?;
+
+    let numerator = target
+        .checked_sub(
+            (effective_margin as i128)
+                .checked_mul(BPS_PRECISION as i128)
+                .ok_or(PerpsError::MathOverflow)?,
+        )
+        .ok_or(PerpsError::MathOverflow)?;
+    let denominator = target
+        .checked_sub(
+            (margin as i128)
+                .checked_mul(liquidation_fee_bps as i128)
+                .ok_or(PerpsError::MathOverflow)?,
+        )
+        .ok_or(PerpsError::MathOverflow)?;
+
+    if numerator <= 0 {
+        return Ok(0);
+    }
+    if denominator <= numerator {
+        return Ok(size);
+    }
+
+    // Round up so the position always ends at or above target
+    let liquidation_size = (size as i128)
+        .checked_mul(numerator)
+        .ok_or(PerpsError::MathOverflow)?
+        .checked_add(denominator - 1)
+        .ok_or(PerpsError::MathOverflow)?
+        .checked_div(denominator)
+        .ok_or(PerpsError::MathOverflow)?;
+
+    Ok((liquidation_size as u64).min(size))
+}
+
+/// Calculate the premium of mark over index implied by open interest skew,
+/// in FUNDING_RATE_PRECISION units, capped at +/-100%.
+/// premium = (long_oi - short_oi) * FUNDING_RATE_PRECISION / skew_scale
+pub fn calculate_premium(
     long_oi: u64,
     short_oi: u64,
+    skew_scale: u64,
 ) -> Result<i64> {
-    let total_oi = (long_oi as u128)
-        .checked_add(short_oi as u128)
+    if skew_scale == 0 {
+        return Ok(0);
+    }
+
+    let skew = (long_oi as i128) - (short_oi as i128);
+    let precision = FUNDING_RATE_PRECISION as i128;
+
+    let premium = skew
+        .checked_mul(precision)
+        .ok_or(PerpsError::MathOverflow)?
+        .checked_div(skew_scale as i128)
+        .ok_or(PerpsError::MathOverflow)?
+        .clamp(-precision, precision);
+
+    Ok(premium as i64)
+}
+
+/// Calculate the mark price from the index price and a premium.
+/// mark = index * (FUNDING_RATE_PRECISION + premium) / FUNDING_RATE_PRECISION
+pub fn calculate_mark_price(index_price: u64, premium: i64) -> Result<u64> {
+    let factor = (FUNDING_RATE_PRECISION as i128)
+        .checked_add(premium as i128)
+        .ok_or(PerpsError::MathOverflow)?
+        .max(0);
+
+    let mark = (index_price as i128)
+        .checked_mul(factor)
+        .ok_or(PerpsError::MathOverflow)?
+        .checked_div(FUNDING_RATE_PRECISION as i128)
         .ok_or(PerpsError::MathOverflow)?;
 
-    if total_oi == 0 {
-        return Ok(0);
+    u64::try_from(mark).map_err(|_| PerpsError::MathOverflow.into())
+}
+
+/// Calculate the execution price of a trade: the oracle price plus the
+/// average of the skew premiums before and after the trade, capped at +/-100%.
+/// Trades that reduce skew fill better than oracle, trades that add to it worse.
+/// premium = (2 * skew + trade_notional) * FUNDING_RATE_PRECISION / (2 * skew_scale)
+/// fill = oracle_price * (FUNDING_RATE_PRECISION + premium) / FUNDING_RATE_PRECISION
+/// where skew = long_oi - short_oi and trade_notional is positive for buys.
+pub fn calculate_fill_price(
+    oracle_price: u64,
+    skew: i128,
+    trade_notional: i128,
+    skew_scale: u64,
+) -> Result<u64> {
+    if skew_scale == 0 {
+        return Ok(oracle_price);
     }
 
-    let diff = (long_oi as i128) - (short_oi as i128);
+    let precision = FUNDING_RATE_PRECISION as i128;
+    let premium = skew
+        .checked_mul(2)
+        .ok_or(PerpsError::MathOverflow)?
+        .checked_add(trade_notional)
+        .ok_or(PerpsError::MathOverflow)?
+        .checked_mul(precision)
+        .ok_or(PerpsError::MathOverflow)?
+        .checked_div((skew_scale as i128) * 2)
+        .ok_or(PerpsError::MathOverflow)?
+        .clamp(-precision, precision);
+
+    calculate_mark_price(oracle_price, premium as i64)
+}
+
+/// Calculate the price at which a trailing stop fires, `distance_bps` from
+/// the best price seen: below the high for longs, above the low for shorts.
+/// stop = reference_price * (BPS_PRECISION -/+ distance_bps) / BPS_PRECISION
+pub fn calculate_trailing_stop_price(
+    direction: Direction,
+    reference_price: u64,
+    distance_bps: u64,
+) -> Result<u64> {
+    let factor = match direction {
+        Direction::Long => BPS_PRECISION
+            .checked_sub(distance_bps)
+            .ok_or(PerpsError::MathOverflow)?,
+        Direction::Short => BPS_PRECISION
+            .checked_add(distance_bps)
+            .ok_or(PerpsError::MathOverflow)?,
+    };
 
-    let rate = diff
-        .checked_mul(FUNDING_RATE_PRECISION as i128)
+    let stop = (reference_price as u128)
+        .checked_mul(factor as u128)
         .ok_or(PerpsError::MathOverflow)?
-        .checked_div(total_oi as i128)
+        .checked_div(BPS_PRECISION as u128)
         .ok_or(PerpsError::MathOverflow)?;
 
-    Ok(rate as i64)
+    u64::try_from(stop).map_err(|_| PerpsError::MathOverflow.into())
 }
 
-/// Calculate funding payment for a position.
-/// payment = size * rate * time_elapsed / (FUNDING_INTERVAL * SIZE_PRECISION)
+/// Calculate funding accrued by a position from the change in its side's
+/// cumulative funding rate since the position was opened. Cumulative rates
+/// accrue per second, so the delta is divided by the funding interval.
+/// payment = notional * (cumulative_rate - entry_cumulative_rate)
+///           / (FUNDING_RATE_PRECISION * FUNDING_INTERVAL)
+/// where notional = size * entry_price / SIZE_PRECISION
 /// Positive means position pays, negative means position receives.
-pub fn calculate_funding_payment(
+pub fn calculate_accrued_funding(
     size: u64,
-    direction: Direction,
-    rate: i64,
-    time_elapsed: i64,
+    entry_price: u64,
+    cumulative_rate: i128,
+    entry_cumulative_rate: i128,
 ) -> Result<i64> {
-    if rate == 0 || time_elapsed == 0 {
+    let rate_delta = cumulative_rate
+        .checked_sub(entry_cumulative_rate)
+        .ok_or(PerpsError::MathOverflow)?;
+
+    if rate_delta == 0 {
         return Ok(0);
     }
 
-    let signed_rate = match direction {
-        Direction::Long => rate as i128,      // longs pay when rate > 0
-        Direction::Short => -(rate as i128),  // shorts receive when rate > 0
-    };
-
-    let payment = (size as i128)
-        .checked_mul(signed_rate)
+    let notional = (size as i128)
+        .checked_mul(entry_price as i128)
         .ok_or(PerpsError::MathOverflow)?
-        .checked_mul(time_elapsed as i128)
+        .checked_div(SIZE_PRECISION as i128)
+        .ok_or(PerpsError::MathOverflow)?;
+
+    let payment = notional
+        .checked_mul(rate_delta)
         .ok_or(PerpsError::MathOverflow)?
         .checked_div(
-            (FUNDING_INTERVAL as i128)
-                .checked_mul(FUNDING_RATE_PRECISION as i128)
+            (FUNDING_RATE_PRECISION as i128)
+                .checked_mul(FUNDING_INTERVAL as i128)
                 .ok_or(PerpsError::MathOverflow)?,
         )
         .ok_or(PerpsError::MathOverflow)?;
 
-    Ok(payment as i64)
+    i64::try_from(payment).map_err(|_| PerpsError::MathOverflow.into())
 }
@@ -1,3 +1,5 @@
 pub mod fixed_point;
+pub mod vamm;
 
 pub use fixed_point::*;
+pub use vamm::*;
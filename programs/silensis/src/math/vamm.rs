@@ -0,0 +1,107 @@
+use anchor_lang::prelude::*;
+use crate::errors::PerpsError;
+use crate::state::Direction;
+
+/// Calculate the price quoted by virtual AMM reserves.
+/// price = quote_reserve * peg_multiplier / base_reserve
+pub fn calculate_vamm_price(
+    base_reserve: u128,
+    quote_reserve: u128,
+    peg_multiplier: u128,
+) -> Result<u64> {
+    let price = quote_reserve
+        .checked_mul(peg_multiplier)
+        .ok_or(PerpsError::MathOverflow)?
+        .checked_div(base_reserve)
+        .ok_or(PerpsError::MathOverflow)?;
+
+    u64::try_from(price).map_err(|_| PerpsError::MathOverflow.into())
+}
+
+/// Swap `size` base units through constant-product reserves
+/// (base_reserve * quote_reserve = k). Longs buy base out of the pool,
+/// shorts sell base into it. Rounds against the trader.
+/// Returns the average fill price and the new base and quote reserves.
+/// fill = |quote_reserve - new_quote_reserve| * peg_multiplier / size
+pub fn calculate_vamm_swap(
+    base_reserve: u128,
+    quote_reserve: u128,
+    peg_multiplier: u128,
+    direction: Direction,
+    size: u64,
+) -> Result<(u64, u128, u128)> {
+    require!(size > 0, PerpsError::ZeroSize);
+
+    let k = base_reserve
+        .checked_mul(quote_reserve)
+        .ok_or(PerpsError::MathOverflow)?;
+    let size = size as u128;
+
+    let new_base_reserve = match direction {
+        Direction::Long => {
+            require!(size < base_reserve, PerpsError::InsufficientLiquidity);
+            base_reserve - size
+        }
+        Direction::Short => base_reserve
+            .checked_add(size)
+            .ok_or(PerpsError::MathOverflow)?,
+    };
+
+    // Round the quote reserve up so the pool never gives away value
+    let new_quote_reserve = k
+        .checked_add(new_base_reserve - 1)
+        .ok_or(PerpsError::MathOverflow)?
+        .checked_div(new_base_reserve)
+        .ok_or(PerpsError::MathOverflow)?;
+
+    let fill_price = match direction {
+        Direction::Long => {
+            let quote_in = new_quote_reserve
+                .checked_sub(quote_reserve)
+                .ok_or(PerpsError::MathOverflow)?;
+            quote_in
+                .checked_mul(peg_multiplier)
+                .ok_or(PerpsError::MathOverflow)?
+                .checked_add(size - 1)
+                .ok_or(PerpsError::MathOverflow)?
+                .checked_div(size)
+                .ok_or(PerpsError::MathOverflow)?
+        }
+        Direction::Short => {
+            let quote_out = quote_reserve
+                .checked_sub(new_quote_reserve)
+                .ok_or(PerpsError::MathOverflow)?;
+            quote_out
+                .checked_mul(peg_multiplier)
+                .ok_or(PerpsError::MathOverflow)?
+                .checked_div(size)
+                .ok_or(PerpsError::MathOverflow)?
+        }
+    };
+
+    let fill_price = u64::try_from(fill_price).map_err(|_| PerpsError::MathOverflow)?;
+    Ok((fill_price, new_base_reserve, new_quote_reserve))
+}
+
+/// Calculate the peg multiplier at which the reserves quote `price`,
+/// rounded toward `current_peg` so the quoted price never overshoots.
+/// peg = price * base_reserve / quote_reserve
+pub fn calculate_vamm_peg(
+    price: u64,
+    base_reserve: u128,
+    quote_reserve: u128,
+    current_peg: u128,
+) -> Result<u128> {
+    let numerator = (price as u128)
+        .checked_mul(base_reserve)
+        .ok_or(PerpsError::MathOverflow)?;
+
+    let peg = numerator
+        .checked_div(quote_reserve)
+        .ok_or(PerpsError::MathOverflow)?;
+    if peg < current_peg && numerator % quote_reserve != 0 {
+        return Ok(peg + 1);
+    }
+
+    Ok(peg)
+}
@@ -0,0 +1,228 @@
+use anchor_lang::prelude::*;
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::state::{Market, OracleSource, PriceFeed, RiskPriceSource};
+
+const PYTH_MAGIC: u32 = 0xa1b2c3d4;
+const PYTH_VERSION: u32 = 2;
+const PYTH_ACCOUNT_TYPE_PRICE: u32 = 3;
+const PYTH_STATUS_TRADING: u32 = 1;
+
+/// Aggregate price read from a Pyth price account (v2 layout).
+pub struct PythPrice {
+    pub ema_price: i64,
+    pub price: i64,
+    pub conf: u64,
+    pub expo: i32,
+    pub status: u32,
+    pub publish_time: i64,
+}
+
+impl PythPrice {
+    /// Parse the fields we need from raw Pyth price account data.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        require!(data.len() >= 240, PerpsError::InvalidOracle);
+        require!(read_u32(data, 0) == PYTH_MAGIC, PerpsError::InvalidOracle);
+        require!(read_u32(data, 4) == PYTH_VERSION, PerpsError::InvalidOracle);
+        require!(
+            read_u32(data, 8) == PYTH_ACCOUNT_TYPE_PRICE,
+            PerpsError::InvalidOracle
+        );
+
+        Ok(Self {
+            expo: read_u32(data, 20) as i32,
+            ema_price: read_u64(data, 48) as i64,
+            publish_time: read_u64(data, 96) as i64,
+            price: read_u64(data, 208) as i64,
+            conf: read_u64(data, 216),
+            status: read_u32(data, 224),
+        })
+    }
+
+    /// Price normalized to PRICE_PRECISION, rejecting non-trading,
+    /// non-positive, low-confidence or stale prices.
+    pub fn get_price(&self, now: i64) -> Result<u64> {
+        require!(
+            self.status == PYTH_STATUS_TRADING && self.price > 0,
+            PerpsError::OracleInvalidPrice
+        );
+
+        // conf / price <= MAX_ORACLE_CONFIDENCE_BPS / BPS_PRECISION
+        require!(
+            (self.conf as u128)
+                .checked_mul(BPS_PRECISION as u128)
+                .ok_or(PerpsError::MathOverflow)?
+                <= (self.price as u128)
+                    .checked_mul(MAX_ORACLE_CONFIDENCE_BPS as u128)
+                    .ok_or(PerpsError::MathOverflow)?,
+            PerpsError::OracleConfidenceTooWide
+        );
+        require!(
+            now - self.publish_time <= MAX_ORACLE_STALENESS,
+            PerpsError::OracleStale
+        );
+
+        self.normalize(self.price)
+    }
+
+    /// EMA price normalized to PRICE_PRECISION, subject to the same checks
+    /// on the current aggregate as `get_price`.
+    pub fn get_ema_price(&self, now: i64) -> Result<u64> {
+        self.get_price(now)?;
+        self.normalize(self.ema_price)
+    }
+
+    /// value * 10^expo expressed with PRICE_DECIMALS decimals
+    fn normalize(&self, value: i64) -> Result<u64> {
+        require!(value > 0, PerpsError::OracleInvalidPrice);
+
+        let shift = self
+            .expo
+            .checked_add(PRICE_DECIMALS as i32)
+            .ok_or(PerpsError::MathOverflow)?;
+        let scale = 10u128
+            .checked_pow(shift.unsigned_abs())
+            .ok_or(PerpsError::MathOverflow)?;
+        let price = if shift >= 0 {
+            (value as u128)
+                .checked_mul(scale)
+                .ok_or(PerpsError::MathOverflow)?
+        } else {
+            (value as u128) / scale
+        };
+        require!(price > 0, PerpsError::OracleInvalidPrice);
+
+        u64::try_from(price).map_err(|_| PerpsError::MathOverflow.into())
+    }
+}
+
+/// Read `market`'s current price from `oracle`, which must be the account
+/// the market is configured to read from.
+pub fn read_oracle_price(market: &Market, oracle: &AccountInfo, now: i64) -> Result<u64> {
+    require_keys_eq!(oracle.key(), market.price_feed, PerpsError::InvalidOracle);
+
+    match market.oracle_source {
+        OracleSource::Internal => load_price_feed(oracle)?.get_price(now),
+        OracleSource::Pyth => load_pyth_price(oracle)?.get_price(now),
+    }
+}
+
+/// Read the price `market` uses for liquidation and funding checks: spot,
+/// TWAP over the market's window or EMA. Pyth oracles provide no TWAP.
+pub fn read_risk_price(market: &Market, oracle: &AccountInfo, now: i64) -> Result<u64> {
+    require_keys_eq!(oracle.key(), market.price_feed, PerpsError::InvalidOracle);
+
+    match (market.oracle_source, market.risk_price_source) {
+        (_, RiskPriceSource::Spot) => read_oracle_price(market, oracle, now),
+        (OracleSource::Internal, RiskPriceSource::Twap) => {
+            load_price_feed(oracle)?.get_twap(now, market.twap_window)
+        }
+        (OracleSource::Internal, RiskPriceSource::Ema) => load_price_feed(oracle)?.get_ema(now),
+        (OracleSource::Pyth, RiskPriceSource::Twap) => err!(PerpsError::TwapUnavailableForPyth),
+        (OracleSource::Pyth, RiskPriceSource::Ema) => load_pyth_price(oracle)?.get_ema_price(now),
+    }
+}
+
+fn load_price_feed(oracle: &AccountInfo) -> Result<PriceFeed> {
+    require_keys_eq!(*oracle.owner, crate::ID, PerpsError::InvalidOracle);
+    PriceFeed::try_deserialize(&mut &oracle.try_borrow_data()?[..])
+}
+
+fn load_pyth_price(oracle: &AccountInfo) -> Result<PythPrice> {
+    require_keys_eq!(*oracle.owner, PYTH_PROGRAM_ID, PerpsError::InvalidOracle);
+    PythPrice::parse(&oracle.try_borrow_data()?)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    /// Pyth v2 price account data with the aggregate and EMA at `price`.
+    fn pyth_account(price: i64, conf: u64, expo: i32, status: u32, publish_time: i64) -> Vec<u8> {
+        let mut data = vec![0u8; 240];
+        data[0..4].copy_from_slice(&PYTH_MAGIC.to_le_bytes());
+        data[4..8].copy_from_slice(&PYTH_VERSION.to_le_bytes());
+        data[8..12].copy_from_slice(&PYTH_ACCOUNT_TYPE_PRICE.to_le_bytes());
+        data[20..24].copy_from_slice(&expo.to_le_bytes());
+        data[48..56].copy_from_slice(&price.to_le_bytes());
+        data[96..104].copy_from_slice(&publish_time.to_le_bytes());
+        data[208..216].copy_from_slice(&price.to_le_bytes());
+        data[216..224].copy_from_slice(&conf.to_le_bytes());
+        data[224..228].copy_from_slice(&status.to_le_bytes());
+        data
+    }
+
+    fn parse(data: &[u8]) -> PythPrice {
+        PythPrice::parse(data).unwrap()
+    }
+
+    #[test]
+    fn normalizes_negative_exponent() {
+        // $100 with 8 decimals
+        let data = pyth_account(10_000_000_000, 1_000_000, -8, PYTH_STATUS_TRADING, NOW);
+        let pyth = parse(&data);
+
+        assert_eq!(pyth.get_price(NOW).unwrap(), 100_000_000);
+        assert_eq!(pyth.get_ema_price(NOW).unwrap(), 100_000_000);
+    }
+
+    #[test]
+    fn rejects_bad_header() {
+        let mut data = pyth_account(10_000_000_000, 0, -8, PYTH_STATUS_TRADING, NOW);
+        data[0] ^= 0xff;
+
+        assert_eq!(
+            PythPrice::parse(&data).err().unwrap(),
+            PerpsError::InvalidOracle.into()
+        );
+    }
+
+    #[test]
+    fn rejects_stale_price() {
+        let data = pyth_account(10_000_000_000, 0, -8, PYTH_STATUS_TRADING, NOW);
+        let pyth = parse(&data);
+
+        assert!(pyth.get_price(NOW + MAX_ORACLE_STALENESS).is_ok());
+        assert_eq!(
+            pyth.get_price(NOW + MAX_ORACLE_STALENESS + 1).unwrap_err(),
+            PerpsError::OracleStale.into()
+        );
+    }
+
+    #[test]
+    fn rejects_non_trading_status() {
+        // 0 = unknown, 2 = halted, 3 = auction
+        for status in [0, 2, 3] {
+            let data = pyth_account(10_000_000_000, 0, -8, status, NOW);
+
+            assert_eq!(
+                parse(&data).get_price(NOW).unwrap_err(),
+                PerpsError::OracleInvalidPrice.into()
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_wide_confidence() {
+        // 2% of $100 is accepted, anything wider is not
+        let max_conf = 10_000_000_000 * MAX_ORACLE_CONFIDENCE_BPS / BPS_PRECISION;
+        let at_limit = pyth_account(10_000_000_000, max_conf, -8, PYTH_STATUS_TRADING, NOW);
+        let too_wide = pyth_account(10_000_000_000, max_conf + 1, -8, PYTH_STATUS_TRADING, NOW);
+
+        assert!(parse(&at_limit).get_price(NOW).is_ok());
+        assert_eq!(
+            parse(&too_wide).get_price(NOW).unwrap_err(),
+            PerpsError::OracleConfidenceTooWide.into()
+        );
+    }
+}
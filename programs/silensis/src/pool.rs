@@ -0,0 +1,39 @@
+use anchor_lang::prelude::*;
+use crate::errors::PerpsError;
+use crate::oracle::read_oracle_price;
+use crate::state::{LiquidityPool, Market};
+
+/// Net asset value of the liquidity pool: its balance less the unrealized
+/// PnL traders hold against it, valued at oracle prices.
+/// Remaining accounts: a (market, price feed) pair for every listed market,
+/// in market index order. Feeds of markets without open interest are not read.
+pub fn load_pool_nav<'info>(
+    pool: &LiquidityPool,
+    market_count: u16,
+    accounts: &'info [AccountInfo<'info>],
+    now: i64,
+) -> Result<u64> {
+    require!(
+        accounts.len() == market_count as usize * 2,
+        PerpsError::InvalidRemainingAccounts
+    );
+
+    let mut unrealized_trader_pnl: i64 = 0;
+    for (market_index, pair) in accounts.chunks(2).enumerate() {
+        let market = Account::<Market>::try_from(&pair[0])?;
+        require!(
+            market.market_index as usize == market_index,
+            PerpsError::InvalidRemainingAccounts
+        );
+        require_keys_eq!(pair[1].key(), market.price_feed, PerpsError::InvalidOracle);
+
+        if market.has_open_interest() {
+            let price = read_oracle_price(&market, &pair[1], now)?;
+            unrealized_trader_pnl = unrealized_trader_pnl
+                .checked_add(market.unrealized_trader_pnl(price)?)
+                .ok_or(PerpsError::MathOverflow)?;
+        }
+    }
+
+    Ok(pool.nav(unrealized_trader_pnl))
+}
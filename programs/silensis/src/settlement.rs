@@ -0,0 +1,310 @@
+use anchor_lang::prelude::*;
+use crate::constants::BPS_PRECISION;
+use crate::errors::PerpsError;
+use crate::events::ShortfallEvent;
+use crate::math::{calculate_accrued_funding, calculate_fee, calculate_notional, calculate_pnl};
+use crate::state::{
+    Direction, GlobalState, InsuranceFund, LiquidityPool, Market, Position, UserVault,
+};
+
+/// Terms of a position being opened.
+pub struct OpenTrade {
+    pub owner: Pubkey,
+    pub direction: Direction,
+    pub size: u64,
+    pub leverage: u64,
+    pub oracle_price: u64,
+    pub cross_headroom: Option<i64>, // initial margin headroom of a cross-margin vault
+}
+
+/// Outcome of opening or growing a position.
+pub struct OpenedPosition {
+    pub fill_price: u64,
+    pub notional: u64,
+    pub margin: u64,
+    pub fee: u64,
+}
+
+/// Open `position` for `trade`: fill through the market, lock margin and
+/// charge the taker fee from `vault`, and add the
+/// position to open interest. Callers check the fill price and set the
+/// position's bump.
+pub fn settle_open(
+    position: &mut Position,
+    vault: &mut UserVault,
+    global: &mut GlobalState,
+    market: &mut Market,
+    insurance_fund: &mut InsuranceFund,
+    liquidity_pool: &mut LiquidityPool,
+    trade: OpenTrade,
+) -> Result<OpenedPosition> {
+    let clock = Clock::get()?;
+
+    let opened = settle_fill(vault, global, market, insurance_fund, liquidity_pool, &trade)?;
+    market.check_position_notional(opened.notional)?;
+
+    position.owner = trade.owner;
+    position.market_index = market.market_index;
+    position.position_id = global.next_position_id;
+    position.direction = trade.direction;
+    position.size = trade.size;
+    position.entry_price = opened.fill_price;
+    position.leverage = trade.leverage;
+    position.margin = opened.margin;
+    position.last_funding_time = clock.unix_timestamp;
+    position.cumulative_funding = market.cumulative_funding_rate(trade.direction);
+    position.stop_loss_price = 0;
+    position.take_profit_price = 0;
+    position.trailing_stop_bps = 0;
+    position.trailing_price = 0;
+    position.is_open = true;
+
+    vault.open_positions = vault
+        .open_positions
+        .checked_add(1)
+        .ok_or(PerpsError::MathOverflow)?;
+
+    global.next_position_id = global
+        .next_position_id
+        .checked_add(1)
+        .ok_or(PerpsError::MathOverflow)?;
+
+    Ok(opened)
+}
+
+/// Fill `trade` through the market, lock its margin and charge the taker
+/// fee from `vault`, and add it to open interest. Shared
+/// by opening a position and growing one; funding is accrued first so the
+/// caller's snapshot is taken at the current index.
+pub fn settle_fill(
+    vault: &mut UserVault,
+    global: &mut GlobalState,
+    market: &mut Market,
+    insurance_fund: &mut InsuranceFund,
+    liquidity_pool: &mut LiquidityPool,
+    trade: &OpenTrade,
+) -> Result<OpenedPosition> {
+    let clock = Clock::get()?;
+
+    // Accrue funding at the pre-trade rate before snapshotting the index
+    market.accrue_funding(clock.unix_timestamp)?;
+
+    // Fill at the oracle price adjusted for price impact, or through the vAMM
+    let fill_price = market.execute_trade(trade.oracle_price, trade.direction, trade.size)?;
+
+    // Calculate notional value and required margin
+    // notional = size * price / SIZE_PRECISION
+    let notional = calculate_notional(trade.size, fill_price)?;
+
+    let margin = notional
+        .checked_div(trade.leverage)
+        .ok_or(PerpsError::MathOverflow)?;
+
+    let fee = calculate_fee(notional, market.taker_fee_bps)?;
+
+    // A cross-margin vault needs equity, less the fee, to cover initial
+    // margin on the new exposure on top of its other positions. An isolated
+    // vault needs free balance to cover margin and fee.
+    match trade.cross_headroom {
+        Some(headroom) => {
+            let initial_margin = (notional as u128)
+                .checked_mul(market.initial_margin_bps() as u128)
+                .ok_or(PerpsError::MathOverflow)?
+                .checked_div(BPS_PRECISION as u128)
+                .ok_or(PerpsError::MathOverflow)? as i64;
+            require!(
+                headroom.checked_sub(fee as i64).ok_or(PerpsError::MathOverflow)? >= initial_margin
+                    && fee <= vault.deposited_amount,
+                PerpsError::InsufficientMargin
+            );
+        }
+        None => require!(
+            margin.checked_add(fee).ok_or(PerpsError::MathOverflow)? <= vault.free_balance()?,
+            PerpsError::InsufficientMargin
+        ),
+    }
+
+    // Lock margin in vault and charge the taker fee
+    vault.locked_margin = vault
+        .locked_margin
+        .checked_add(margin)
+        .ok_or(PerpsError::MathOverflow)?;
+    vault.deposited_amount = vault
+        .deposited_amount
+        .checked_sub(fee)
+        .ok_or(PerpsError::MathOverflow)?;
+    vault.increase_open_notional(notional, global.max_user_notional)?;
+
+    global.collect_fee(insurance_fund, liquidity_pool, fee)?;
+
+    // Update open interest, within the market's caps
+    market.increase_open_interest(trade.direction, trade.size, notional)?;
+
+    Ok(OpenedPosition {
+        fill_price,
+        notional,
+        margin,
+        fee,
+    })
+}
+
+/// Size and oracle price of a position being reduced or closed.
+pub struct CloseTrade {
+    pub size: u64,
+    pub oracle_price: u64,
+}
+
+/// Outcome of closing all or part of a position.
+pub struct ClosedPosition {
+    pub fill_price: u64,
+    pub pnl: i64, // net of funding
+    pub funding_payment: i64,
+    pub fee: u64,
+    pub settlement: u64, // released margin plus PnL, less the fee, credited back to the vault
+}
+
+/// Close `trade.size` of `position`, all of it or a slice: exit through the
+/// market, settle PnL and funding on the closed size against its share of
+/// margin less the taker fee and hand any shortfall to the insurance fund
+/// and the liquidity pool. A partial close keeps the entry price and
+/// funding snapshot on the rest.
+pub fn settle_close(
+    position: &mut Position,
+    vault: &mut UserVault,
+    global: &mut GlobalState,
+    market: &mut Market,
+    insurance_fund: &mut InsuranceFund,
+    liquidity_pool: &mut LiquidityPool,
+    trade: CloseTrade,
+) -> Result<ClosedPosition> {
+    let size = trade.size;
+    require!(size > 0 && size <= position.size, PerpsError::InvalidParameter);
+    let is_full = size == position.size;
+
+    // The trailing stop follows every price the position is touched at
+    position.update_trailing_price(trade.oracle_price);
+
+    // Bring the funding index up to date before settling against it
+    market.accrue_funding(Clock::get()?.unix_timestamp)?;
+
+    // Exit at the oracle price adjusted for price impact, or through the vAMM
+    let fill_price = market.execute_trade(trade.oracle_price, position.direction.opposite(), size)?;
+
+    // Realize PnL net of funding accrued since open on the closed size
+    let price_pnl = calculate_pnl(position.direction, size, position.entry_price, fill_price)?;
+
+    let funding_payment = calculate_accrued_funding(
+        size,
+        position.entry_price,
+        market.cumulative_funding_rate(position.direction),
+        position.cumulative_funding,
+    )?;
+
+    // Profit is paid only as far as the liquidity pool can cover it
+    let pnl = liquidity_pool.cap_trader_pnl(
+        price_pnl
+            .checked_sub(funding_payment)
+            .ok_or(PerpsError::MathOverflow)?,
+    );
+
+    // Release the matching share of margin
+    // margin_released = margin * size / position_size
+    let margin = (position.margin as u128)
+        .checked_mul(size as u128)
+        .ok_or(PerpsError::MathOverflow)?
+        .checked_div(position.size as u128)
+        .ok_or(PerpsError::MathOverflow)? as u64;
+
+    // Calculate closed notional for OI update
+    let notional = calculate_notional(size, position.entry_price)?;
+
+    // Taker fee on exit notional
+    let exit_notional = calculate_notional(size, fill_price)?;
+    let fee = calculate_fee(exit_notional, market.taker_fee_bps)?;
+
+    // Settle: unlock margin and credit margin + pnl less the taker fee
+    // (clamped to 0 minimum). A fee the position cannot pay is part of its
+    // shortfall, so it is never waived.
+    let pnl_after_fee = pnl
+        .checked_sub(fee as i64)
+        .ok_or(PerpsError::MathOverflow)?;
+    let (settlement, shortfall) = vault.settle_margin(margin, pnl_after_fee)?;
+    if is_full {
+        vault.open_positions = vault.open_positions.saturating_sub(1);
+    }
+    vault.decrease_open_notional(notional);
+
+    global.collect_fee(insurance_fund, liquidity_pool, fee)?;
+
+    settle_position_pnl(position, global, insurance_fund, liquidity_pool, pnl, shortfall)?;
+
+    // Update market open interest
+    market.decrease_open_interest(position.direction, size, notional);
+
+    if is_full {
+        position.is_open = false;
+    } else {
+        position.size -= size;
+        position.margin = position
+            .margin
+            .checked_sub(margin)
+            .ok_or(PerpsError::MathOverflow)?;
+    }
+
+    Ok(ClosedPosition {
+        fill_price,
+        pnl,
+        funding_payment,
+        fee,
+        settlement,
+    })
+}
+
+/// Cover a trader's `shortfall` from the insurance fund and hand their
+/// realized `pnl`, with any bad debt left over, to the liquidity pool.
+/// Returns the amounts covered by insurance and left as bad debt.
+pub fn settle_pool_pnl(
+    global: &mut GlobalState,
+    insurance_fund: &mut InsuranceFund,
+    liquidity_pool: &mut LiquidityPool,
+    pnl: i64,
+    shortfall: u64,
+) -> Result<(u64, u64)> {
+    let (covered, bad_debt) = if shortfall > 0 {
+        global.absorb_shortfall(insurance_fund, shortfall)?
+    } else {
+        (0, 0)
+    };
+
+    // The liquidity pool takes the other side of the realized PnL
+    liquidity_pool.settle_trader_pnl(pnl, bad_debt)?;
+
+    Ok((covered, bad_debt))
+}
+
+/// `settle_pool_pnl` for PnL realized on `position`, reporting any shortfall.
+pub fn settle_position_pnl(
+    position: &Position,
+    global: &mut GlobalState,
+    insurance_fund: &mut InsuranceFund,
+    liquidity_pool: &mut LiquidityPool,
+    pnl: i64,
+    shortfall: u64,
+) -> Result<()> {
+    let (covered, bad_debt) =
+        settle_pool_pnl(global, insurance_fund, liquidity_pool, pnl, shortfall)?;
+
+    if shortfall > 0 {
+        emit!(ShortfallEvent {
+            market_index: position.market_index,
+            position_id: position.position_id,
+            owner: position.owner,
+            shortfall,
+            covered_by_insurance: covered,
+            bad_debt,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+    }
+
+    Ok(())
+}
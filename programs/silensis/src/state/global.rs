@@ -1,20 +1,25 @@
 use anchor_lang::prelude::*;
+use crate::constants::{
+    MAX_ORACLE_STALENESS, MAX_PRICE_PUBLISHERS, PRICE_EMA_PERIOD, PRICE_HISTORY_LEN,
+    PRICE_OBSERVATION_INTERVAL,
+};
+use crate::errors::PerpsError;
+use crate::state::{InsuranceFund, LiquidityPool};
 
 #[account]
 #[derive(Default)]
 pub struct GlobalState {
     pub authority: Pubkey,
+    pub pending_authority: Pubkey,
+    pub guardian: Pubkey,
     pub usdc_mint: Pubkey,
     pub treasury: Pubkey,
-    pub total_long_oi: u64,
-    pub total_short_oi: u64,
-    pub last_funding_time: i64,
-    pub cumulative_funding_rate_long: i128,
-    pub cumulative_funding_rate_short: i128,
-    pub max_leverage: u64,
-    pub maintenance_margin_bps: u64,
-    pub liquidation_fee_bps: u64,
     pub next_position_id: u64,
+    pub next_order_id: u64,
+    pub protocol_fee_balance: u64,
+    pub total_bad_debt: u64,
+    pub max_user_notional: u64, // open notional cap per user across markets
+    pub market_count: u16,
     pub is_paused: bool,
     pub bump: u8,
 }
@@ -22,34 +27,269 @@ pub struct GlobalState {
 impl GlobalState {
     pub const LEN: usize = 8 // discriminator
         + 32  // authority
+        + 32  // pending_authority
+        + 32  // guardian
         + 32  // usdc_mint
         + 32  // treasury
-        + 8   // total_long_oi
-        + 8   // total_short_oi
-        + 8   // last_funding_time
-        + 16  // cumulative_funding_rate_long
-        + 16  // cumulative_funding_rate_short
-        + 8   // max_leverage
-        + 8   // maintenance_margin_bps
-        + 8   // liquidation_fee_bps
         + 8   // next_position_id
+        + 8   // next_order_id
+        + 8   // protocol_fee_balance
+        + 8   // total_bad_debt
+        + 8   // max_user_notional
+        + 2   // market_count
         + 1   // is_paused
         + 1;  // bump
+
+    /// Book a trading fee: the insurance fund takes its share, the liquidity
+    /// pool its share of the rest, and the remainder is protocol revenue.
+    pub fn collect_fee(
+        &mut self,
+        insurance_fund: &mut InsuranceFund,
+        liquidity_pool: &mut LiquidityPool,
+        fee: u64,
+    ) -> Result<()> {
+        let remaining = insurance_fund.take_fee_share(fee)?;
+        let protocol_fee = liquidity_pool.take_fee_share(remaining)?;
+        self.protocol_fee_balance = self
+            .protocol_fee_balance
+            .checked_add(protocol_fee)
+            .ok_or(PerpsError::MathOverflow)?;
+
+        Ok(())
+    }
+
+    /// Cover a shortfall from the insurance fund and record the rest as bad debt.
+    /// Returns the amounts covered and left as bad debt.
+    pub fn absorb_shortfall(
+        &mut self,
+        insurance_fund: &mut InsuranceFund,
+        shortfall: u64,
+    ) -> Result<(u64, u64)> {
+        let covered = insurance_fund.cover_shortfall(shortfall)?;
+        let bad_debt = shortfall - covered;
+        self.total_bad_debt = self
+            .total_bad_debt
+            .checked_add(bad_debt)
+            .ok_or(PerpsError::MathOverflow)?;
+
+        Ok((covered, bad_debt))
+    }
+}
+
+/// Latest price pushed by one publisher.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct PriceSubmission {
+    pub publisher: Pubkey,
+    pub price: u64,     // 6 decimals
+    pub timestamp: i64,
+}
+
+/// Aggregate price recorded in the feed's history.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct PriceObservation {
+    pub timestamp: i64,
+    pub price: u64,             // 6 decimals
+    pub cumulative_price: u128, // PriceFeed::cumulative_price at `timestamp`
 }
 
 #[account]
 #[derive(Default)]
 pub struct PriceFeed {
-    pub authority: Pubkey,
-    pub price: u64,        // 6 decimals
+    pub submissions: [PriceSubmission; MAX_PRICE_PUBLISHERS], // first publisher_count in use
+    pub publisher_count: u8,
+    pub min_publishers: u8, // fresh submissions required for a price
+    pub price: u64,         // median as of the latest submission, 6 decimals
     pub timestamp: i64,
+    pub ema_price: u64,
+    pub cumulative_price: u128, // sum of price * seconds held, up to `timestamp`
+    pub observations: [PriceObservation; PRICE_HISTORY_LEN], // ring buffer
+    pub observation_index: u8, // slot of the newest observation
+    pub observation_count: u8,
     pub bump: u8,
 }
 
 impl PriceFeed {
     pub const LEN: usize = 8 // discriminator
-        + 32  // authority
+        + MAX_PRICE_PUBLISHERS * (32 + 8 + 8) // submissions
+        + 1   // publisher_count
+        + 1   // min_publishers
         + 8   // price
         + 8   // timestamp
+        + 8   // ema_price
+        + 16  // cumulative_price
+        + PRICE_HISTORY_LEN * (8 + 8 + 16) // observations
+        + 1   // observation_index
+        + 1   // observation_count
         + 1;  // bump
 }
+
+impl PriceFeed {
+    /// Replace the publisher set. Submissions from publishers that stay in
+    /// the set are kept; new publishers start with no submission.
+    pub fn set_publishers(&mut self, publishers: &[Pubkey], min_publishers: u8) -> Result<()> {
+        require!(
+            !publishers.is_empty() && publishers.len() <= MAX_PRICE_PUBLISHERS,
+            PerpsError::InvalidParameter
+        );
+        require!(
+            min_publishers > 0 && min_publishers as usize <= publishers.len(),
+            PerpsError::InvalidParameter
+        );
+
+        let mut submissions = [PriceSubmission::default(); MAX_PRICE_PUBLISHERS];
+        for (i, publisher) in publishers.iter().enumerate() {
+            require!(
+                !publishers[..i].contains(publisher),
+                PerpsError::InvalidParameter
+            );
+            submissions[i] = self
+                .active_submissions()
+                .iter()
+                .find(|s| s.publisher == *publisher)
+                .copied()
+                .unwrap_or(PriceSubmission {
+                    publisher: *publisher,
+                    ..Default::default()
+                });
+        }
+
+        self.submissions = submissions;
+        self.publisher_count = publishers.len() as u8;
+        self.min_publishers = min_publishers;
+        Ok(())
+    }
+
+    /// Record `publisher`'s price and refresh the stored median.
+    pub fn submit(&mut self, publisher: Pubkey, price: u64, now: i64) -> Result<()> {
+        let count = self.publisher_count as usize;
+        let submission = self.submissions[..count]
+            .iter_mut()
+            .find(|s| s.publisher == publisher)
+            .ok_or(PerpsError::Unauthorized)?;
+        submission.price = price;
+        submission.timestamp = now;
+
+        if let Ok(median) = self.get_price(now) {
+            self.record_price(median, now)?;
+        }
+        Ok(())
+    }
+
+    /// Make `price` the feed's aggregate price as of `now`, rolling the
+    /// previous price into the cumulative sum and EMA and storing an
+    /// observation at most once per PRICE_OBSERVATION_INTERVAL.
+    fn record_price(&mut self, price: u64, now: i64) -> Result<()> {
+        if self.timestamp > 0 {
+            let elapsed = now.saturating_sub(self.timestamp).max(0);
+            self.cumulative_price = self
+                .cumulative_price
+                .checked_add(
+                    (self.price as u128)
+                        .checked_mul(elapsed as u128)
+                        .ok_or(PerpsError::MathOverflow)?,
+                )
+                .ok_or(PerpsError::MathOverflow)?;
+
+            // Move the EMA toward the new price in proportion to time elapsed
+            let delta = (price as i128 - self.ema_price as i128)
+                .checked_mul(elapsed.min(PRICE_EMA_PERIOD) as i128)
+                .ok_or(PerpsError::MathOverflow)?
+                / PRICE_EMA_PERIOD as i128;
+            self.ema_price = (self.ema_price as i128 + delta) as u64;
+        } else {
+            self.ema_price = price;
+        }
+        self.price = price;
+        self.timestamp = now;
+
+        let newest = self.observations[self.observation_index as usize];
+        if self.observation_count == 0 || now - newest.timestamp >= PRICE_OBSERVATION_INTERVAL {
+            if self.observation_count > 0 {
+                self.observation_index = ((self.observation_index as usize + 1)
+                    % PRICE_HISTORY_LEN) as u8;
+            }
+            self.observations[self.observation_index as usize] = PriceObservation {
+                timestamp: now,
+                price,
+                cumulative_price: self.cumulative_price,
+            };
+            self.observation_count = (self.observation_count as usize + 1)
+                .min(PRICE_HISTORY_LEN) as u8;
+        }
+        Ok(())
+    }
+
+    /// Time-weighted average of the aggregate price over the last `window`
+    /// seconds, or over the stored history if it is shorter.
+    pub fn get_twap(&self, now: i64, window: i64) -> Result<u64> {
+        self.get_price(now)?;
+
+        let cumulative_now = self
+            .cumulative_price
+            .checked_add(
+                (self.price as u128)
+                    .checked_mul(now.saturating_sub(self.timestamp).max(0) as u128)
+                    .ok_or(PerpsError::MathOverflow)?,
+            )
+            .ok_or(PerpsError::MathOverflow)?;
+
+        // Newest observation at or before the window start, else the oldest
+        let window_start = now - window;
+        let mut start = self.observations[self.observation_index as usize];
+        for age in 1..self.observation_count as usize {
+            if start.timestamp <= window_start {
+                break;
+            }
+            start = self.observations
+                [(self.observation_index as usize + PRICE_HISTORY_LEN - age) % PRICE_HISTORY_LEN];
+        }
+
+        let elapsed = now - start.timestamp;
+        if elapsed <= 0 {
+            return Ok(self.price);
+        }
+        let twap = cumulative_now
+            .checked_sub(start.cumulative_price)
+            .ok_or(PerpsError::MathOverflow)?
+            / elapsed as u128;
+        u64::try_from(twap).map_err(|_| PerpsError::MathOverflow.into())
+    }
+
+    /// Exponential moving average of the aggregate price.
+    pub fn get_ema(&self, now: i64) -> Result<u64> {
+        self.get_price(now)?;
+        Ok(self.ema_price)
+    }
+
+    /// Median of fresh submissions, rejecting a stale feed or one with
+    /// fewer fresh submissions than the quorum.
+    pub fn get_price(&self, now: i64) -> Result<u64> {
+        let mut prices = [0u64; MAX_PRICE_PUBLISHERS];
+        let mut fresh = 0;
+        for submission in self.active_submissions() {
+            if submission.price > 0 && now - submission.timestamp <= MAX_ORACLE_STALENESS {
+                prices[fresh] = submission.price;
+                fresh += 1;
+            }
+        }
+        require!(fresh > 0, PerpsError::OracleStale);
+        require!(
+            fresh >= self.min_publishers as usize,
+            PerpsError::OracleQuorumNotMet
+        );
+
+        let prices = &mut prices[..fresh];
+        prices.sort_unstable();
+        let mid = fresh / 2;
+        if fresh % 2 == 1 {
+            Ok(prices[mid])
+        } else {
+            // Midpoint of the two middle prices without overflow
+            Ok(prices[mid - 1] + (prices[mid] - prices[mid - 1]) / 2)
+        }
+    }
+
+    fn active_submissions(&self) -> &[PriceSubmission] {
+        &self.submissions[..self.publisher_count as usize]
+    }
+}
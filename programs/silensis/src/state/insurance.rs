@@ -0,0 +1,45 @@
+use anchor_lang::prelude::*;
+use crate::errors::PerpsError;
+use crate::math::calculate_fee;
+
+#[account]
+#[derive(Default)]
+pub struct InsuranceFund {
+    pub balance: u64,
+    pub fee_share_bps: u64,
+    pub total_shortfall_covered: u64,
+    pub bump: u8,
+}
+
+impl InsuranceFund {
+    pub const LEN: usize = 8 // discriminator
+        + 8   // balance
+        + 8   // fee_share_bps
+        + 8   // total_shortfall_covered
+        + 1;  // bump
+
+    /// Credit the fund's share of a fee. Returns the part of the fee left
+    /// for its other recipient.
+    pub fn take_fee_share(&mut self, fee: u64) -> Result<u64> {
+        let share = calculate_fee(fee, self.fee_share_bps)?;
+        self.balance = self
+            .balance
+            .checked_add(share)
+            .ok_or(PerpsError::MathOverflow)?;
+
+        Ok(fee - share)
+    }
+
+    /// Cover as much of a shortfall as the fund balance allows.
+    /// Returns the amount covered; the rest is bad debt.
+    pub fn cover_shortfall(&mut self, shortfall: u64) -> Result<u64> {
+        let covered = shortfall.min(self.balance);
+        self.balance -= covered;
+        self.total_shortfall_covered = self
+            .total_shortfall_covered
+            .checked_add(covered)
+            .ok_or(PerpsError::MathOverflow)?;
+
+        Ok(covered)
+    }
+}
@@ -0,0 +1,100 @@
+use anchor_lang::prelude::*;
+use crate::errors::PerpsError;
+use crate::math::calculate_fee;
+
+/// Counterparty to traders. LPs own `balance`, held in the treasury, in
+/// proportion to their share tokens. Trader profit is paid only up to the
+/// balance, so other users' collateral never funds a payout.
+#[account]
+#[derive(Default)]
+pub struct LiquidityPool {
+    pub lp_mint: Pubkey,
+    pub balance: u64,        // USDC owed to LPs before unrealized trader PnL
+    pub fee_share_bps: u64,  // of fees left after the insurance share
+    pub total_fees_earned: u64,
+    pub bump: u8,
+}
+
+impl LiquidityPool {
+    pub const LEN: usize = 8 // discriminator
+        + 32  // lp_mint
+        + 8   // balance
+        + 8   // fee_share_bps
+        + 8   // total_fees_earned
+        + 1;  // bump
+
+    /// Credit the pool's share of a fee. Returns the part of the fee left
+    /// for the protocol.
+    pub fn take_fee_share(&mut self, fee: u64) -> Result<u64> {
+        let share = calculate_fee(fee, self.fee_share_bps)?;
+        self.balance = self
+            .balance
+            .checked_add(share)
+            .ok_or(PerpsError::MathOverflow)?;
+        self.total_fees_earned = self
+            .total_fees_earned
+            .checked_add(share)
+            .ok_or(PerpsError::MathOverflow)?;
+
+        Ok(fee - share)
+    }
+
+    /// Trader profit the pool can pay: realized PnL capped at its balance.
+    /// Losses pass through unchanged.
+    pub fn cap_trader_pnl(&self, pnl: i64) -> i64 {
+        pnl.min(i64::try_from(self.balance).unwrap_or(i64::MAX))
+    }
+
+    /// Take the other side of a trader's realized PnL: pay out profit,
+    /// collect loss. Bad debt the trader left unpaid is the pool's loss.
+    /// Profit must already be capped with `cap_trader_pnl`.
+    pub fn settle_trader_pnl(&mut self, pnl: i64, bad_debt: u64) -> Result<()> {
+        self.balance = if pnl >= 0 {
+            self.balance
+                .checked_sub(pnl as u64)
+                .ok_or(PerpsError::InsufficientPoolLiquidity)?
+        } else {
+            self.balance
+                .checked_add(pnl.unsigned_abs())
+                .ok_or(PerpsError::MathOverflow)?
+        };
+        self.balance = self.balance.saturating_sub(bad_debt);
+
+        Ok(())
+    }
+
+    /// Net asset value: balance less what the pool owes on open positions.
+    pub fn nav(&self, unrealized_trader_pnl: i64) -> u64 {
+        (self.balance as i128 - unrealized_trader_pnl as i128).max(0) as u64
+    }
+
+    /// Shares minted for a deposit of `amount` at `nav`.
+    /// shares = amount * share_supply / nav, 1:1 for the first deposit.
+    /// Existing shares worth nothing cannot price a deposit.
+    pub fn shares_for_deposit(amount: u64, nav: u64, share_supply: u64) -> Result<u64> {
+        if share_supply == 0 {
+            return Ok(amount);
+        }
+        require!(nav > 0, PerpsError::PoolInsolvent);
+
+        let shares = (amount as u128)
+            .checked_mul(share_supply as u128)
+            .ok_or(PerpsError::MathOverflow)?
+            .checked_div(nav as u128)
+            .ok_or(PerpsError::MathOverflow)?;
+
+        u64::try_from(shares).map_err(|_| PerpsError::MathOverflow.into())
+    }
+
+    /// USDC returned for burning `shares` at `nav`.
+    /// amount = shares * nav / share_supply
+    pub fn amount_for_shares(shares: u64, nav: u64, share_supply: u64) -> Result<u64> {
+        let amount = (shares as u128)
+            .checked_mul(nav as u128)
+            .ok_or(PerpsError::MathOverflow)?
+            .checked_div(share_supply as u128)
+            .ok_or(PerpsError::MathOverflow)?;
+
+        u64::try_from(amount).map_err(|_| PerpsError::MathOverflow.into())
+    }
+}
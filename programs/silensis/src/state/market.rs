@@ -0,0 +1,452 @@
+use anchor_lang::prelude::*;
+use crate::constants::*;
+use crate::errors::PerpsError;
+use crate::math::{
+    calculate_fill_price, calculate_mark_price, calculate_notional, calculate_premium,
+    calculate_vamm_peg, calculate_vamm_price, calculate_vamm_swap,
+};
+use crate::state::Direction;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OracleSource {
+    /// Program-owned PriceFeed updated by `set_price`
+    #[default]
+    Internal,
+    /// Pyth price account
+    Pyth,
+}
+
+/// Price used for liquidation and funding checks.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RiskPriceSource {
+    #[default]
+    Spot,
+    /// Time-weighted average over the market's `twap_window`
+    Twap,
+    Ema,
+}
+
+/// Source of execution prices for trades.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PricingMode {
+    /// Oracle price adjusted for the trade's impact on OI skew
+    #[default]
+    Oracle,
+    /// Constant-product virtual AMM reserves with a peg multiplier
+    Vamm,
+}
+
+#[account]
+#[derive(Default)]
+pub struct Market {
+    pub market_index: u16,
+    pub price_feed: Pubkey, // account prices are read from
+    pub oracle_source: OracleSource,
+    pub total_long_oi: u64,  // entry notional
+    pub total_short_oi: u64, // entry notional
+    pub total_long_size: u64,
+    pub total_short_size: u64,
+    pub last_funding_time: i64,
+    pub cumulative_funding_rate_long: i128,
+    pub cumulative_funding_rate_short: i128,
+    pub max_leverage: u64,
+    pub maintenance_margin_bps: u64,
+    pub liquidation_fee_bps: u64,
+    pub full_liquidation_margin_bps: u64,
+    pub taker_fee_bps: u64,
+    pub risk_price_source: RiskPriceSource,
+    pub twap_window: i64, // seconds
+    pub skew_scale: u64,  // net OI notional at which the mark premium is 100%
+    pub max_funding_rate_bps: u64, // per funding interval
+    pub max_open_interest: u64, // long + short notional
+    pub max_long_oi: u64,
+    pub max_short_oi: u64,
+    pub max_position_notional: u64,
+    pub pricing_mode: PricingMode,
+    pub base_reserve: u128,  // vAMM virtual base, SIZE_PRECISION
+    pub quote_reserve: u128, // vAMM virtual quote, SIZE_PRECISION
+    pub peg_multiplier: u128, // vAMM price at balanced reserves, PRICE_PRECISION
+    pub vamm_net_base: i64,  // traders' net long base against the vAMM
+    pub repeg_budget: u64,   // protocol fees repegs may still spend
+    pub bump: u8,
+}
+
+impl Market {
+    pub const LEN: usize = 8 // discriminator
+        + 2   // market_index
+        + 32  // price_feed
+        + 1   // oracle_source
+        + 8   // total_long_oi
+        + 8   // total_short_oi
+        + 8   // total_long_size
+        + 8   // total_short_size
+        + 8   // last_funding_time
+        + 16  // cumulative_funding_rate_long
+        + 16  // cumulative_funding_rate_short
+        + 8   // max_leverage
+        + 8   // maintenance_margin_bps
+        + 8   // liquidation_fee_bps
+        + 8   // full_liquidation_margin_bps
+        + 8   // taker_fee_bps
+        + 1   // risk_price_source
+        + 8   // twap_window
+        + 8   // skew_scale
+        + 8   // max_funding_rate_bps
+        + 8   // max_open_interest
+        + 8   // max_long_oi
+        + 8   // max_short_oi
+        + 8   // max_position_notional
+        + 1   // pricing_mode
+        + 16  // base_reserve
+        + 16  // quote_reserve
+        + 16  // peg_multiplier
+        + 8   // vamm_net_base
+        + 8   // repeg_budget
+        + 1;  // bump
+}
+
+impl Market {
+    /// Validate a set of risk parameters before they are written to a market.
+    /// A position opened at max leverage must start at or above maintenance
+    /// margin, the liquidation fee must be covered by maintenance margin, and
+    /// full liquidation must trigger below maintenance margin.
+    pub fn validate_risk_params(
+        max_leverage: u64,
+        maintenance_margin_bps: u64,
+        liquidation_fee_bps: u64,
+        full_liquidation_margin_bps: u64,
+    ) -> Result<()> {
+        require!(
+            max_leverage > 0 && max_leverage <= MAX_LEVERAGE,
+            PerpsError::InvalidLeverage
+        );
+        require!(
+            maintenance_margin_bps > 0 && maintenance_margin_bps < BPS_PRECISION,
+            PerpsError::InvalidParameter
+        );
+        require!(
+            maintenance_margin_bps
+                .checked_mul(max_leverage)
+                .ok_or(PerpsError::MathOverflow)?
+                <= BPS_PRECISION,
+            PerpsError::InvalidParameter
+        );
+        require!(
+            liquidation_fee_bps < maintenance_margin_bps,
+            PerpsError::InvalidParameter
+        );
+        require!(
+            full_liquidation_margin_bps < maintenance_margin_bps,
+            PerpsError::InvalidParameter
+        );
+        Ok(())
+    }
+
+    /// Validate trading fee parameters before they are written to a market.
+    pub fn validate_fee_params(taker_fee_bps: u64) -> Result<()> {
+        require!(
+            taker_fee_bps <= MAX_TAKER_FEE_BPS,
+            PerpsError::InvalidParameter
+        );
+        Ok(())
+    }
+
+    /// Validate the averaging window used for TWAP risk prices.
+    pub fn validate_price_params(twap_window: i64) -> Result<()> {
+        require!(
+            twap_window > 0 && twap_window <= MAX_TWAP_WINDOW,
+            PerpsError::InvalidParameter
+        );
+        Ok(())
+    }
+
+    /// Validate that the oracle can serve the risk price source: only the
+    /// internal PriceFeed keeps the observations a TWAP needs.
+    pub fn validate_risk_price_source(
+        oracle_source: OracleSource,
+        risk_price_source: RiskPriceSource,
+    ) -> Result<()> {
+        require!(
+            !(oracle_source == OracleSource::Pyth && risk_price_source == RiskPriceSource::Twap),
+            PerpsError::TwapUnavailableForPyth
+        );
+        Ok(())
+    }
+
+    /// Validate the mark premium scale and funding rate cap.
+    pub fn validate_funding_params(skew_scale: u64, max_funding_rate_bps: u64) -> Result<()> {
+        require!(skew_scale > 0, PerpsError::InvalidParameter);
+        require!(
+            max_funding_rate_bps <= BPS_PRECISION,
+            PerpsError::InvalidParameter
+        );
+        Ok(())
+    }
+
+    /// Validate open interest caps: each side fits within the market total
+    /// and a single position fits within either side.
+    pub fn validate_oi_params(
+        max_open_interest: u64,
+        max_long_oi: u64,
+        max_short_oi: u64,
+        max_position_notional: u64,
+    ) -> Result<()> {
+        require!(
+            max_long_oi > 0 && max_long_oi <= max_open_interest,
+            PerpsError::InvalidParameter
+        );
+        require!(
+            max_short_oi > 0 && max_short_oi <= max_open_interest,
+            PerpsError::InvalidParameter
+        );
+        require!(
+            max_position_notional > 0
+                && max_position_notional <= max_long_oi.min(max_short_oi),
+            PerpsError::InvalidParameter
+        );
+        Ok(())
+    }
+
+    /// Require a position's notional to fit within the market's per-position cap.
+    pub fn check_position_notional(&self, notional: u64) -> Result<()> {
+        require!(
+            notional <= self.max_position_notional,
+            PerpsError::PositionNotionalCapExceeded
+        );
+        Ok(())
+    }
+
+    /// Mark price: the index price plus a premium from the open interest skew.
+    pub fn mark_price(&self, index_price: u64) -> Result<u64> {
+        let premium = calculate_premium(self.total_long_oi, self.total_short_oi, self.skew_scale)?;
+        calculate_mark_price(index_price, premium)
+    }
+
+    /// Execution price for a trade of `size` in `direction`. Oracle-priced
+    /// markets fill at the oracle price after the trade's impact on the open
+    /// interest skew; vAMM markets swap through their reserves.
+    /// Must run before the trade changes open interest.
+    pub fn execute_trade(
+        &mut self,
+        oracle_price: u64,
+        direction: Direction,
+        size: u64,
+    ) -> Result<u64> {
+        match self.pricing_mode {
+            PricingMode::Oracle => {
+                let notional = calculate_notional(size, oracle_price)? as i128;
+                let trade_notional = match direction {
+                    Direction::Long => notional,
+                    Direction::Short => -notional,
+                };
+                let skew = (self.total_long_oi as i128) - (self.total_short_oi as i128);
+                calculate_fill_price(oracle_price, skew, trade_notional, self.skew_scale)
+            }
+            PricingMode::Vamm => {
+                let (fill_price, base_reserve, quote_reserve) = calculate_vamm_swap(
+                    self.base_reserve,
+                    self.quote_reserve,
+                    self.peg_multiplier,
+                    direction,
+                    size,
+                )?;
+                self.base_reserve = base_reserve;
+                self.quote_reserve = quote_reserve;
+
+                let size = i64::try_from(size).map_err(|_| PerpsError::MathOverflow)?;
+                self.vamm_net_base = match direction {
+                    Direction::Long => self.vamm_net_base.checked_add(size),
+                    Direction::Short => self.vamm_net_base.checked_sub(size),
+                }
+                .ok_or(PerpsError::MathOverflow)?;
+
+                Ok(fill_price)
+            }
+        }
+    }
+
+    /// Price currently quoted by the vAMM reserves.
+    pub fn vamm_price(&self) -> Result<u64> {
+        calculate_vamm_price(self.base_reserve, self.quote_reserve, self.peg_multiplier)
+    }
+
+    /// Move the vAMM peg toward `target_price`. Traders' net position gains
+    /// or loses with the price move; the pool pays the gain, so the move
+    /// stops where it would cost more than `max_cost`. Returns the cost
+    /// (negative when traders lose) and charges it to the repeg budget.
+    pub fn repeg(&mut self, target_price: u64, max_cost: u64) -> Result<i64> {
+        let old_price = self.vamm_price()?;
+        let net_base = self.vamm_net_base as i128;
+
+        let mut price_delta = (target_price as i128) - (old_price as i128);
+        let full_cost = net_base
+            .checked_mul(price_delta)
+            .ok_or(PerpsError::MathOverflow)?
+            / SIZE_PRECISION as i128;
+        if full_cost > max_cost as i128 {
+            // max_delta = max_cost * SIZE_PRECISION / |net_base|
+            let max_delta = (max_cost as i128)
+                .checked_mul(SIZE_PRECISION as i128)
+                .ok_or(PerpsError::MathOverflow)?
+                / net_base.abs();
+            price_delta = max_delta * price_delta.signum();
+        }
+
+        let new_price = u64::try_from((old_price as i128) + price_delta)
+            .map_err(|_| PerpsError::MathOverflow)?;
+        self.peg_multiplier = calculate_vamm_peg(
+            new_price,
+            self.base_reserve,
+            self.quote_reserve,
+            self.peg_multiplier,
+        )?;
+        require!(self.peg_multiplier > 0, PerpsError::InvalidParameter);
+
+        let price_change = (self.vamm_price()? as i128) - (old_price as i128);
+        let cost = net_base
+            .checked_mul(price_change)
+            .ok_or(PerpsError::MathOverflow)?
+            / SIZE_PRECISION as i128;
+        let cost = i64::try_from(cost).map_err(|_| PerpsError::MathOverflow)?;
+        if cost > 0 {
+            self.repeg_budget = self.repeg_budget.saturating_sub(cost as u64);
+        }
+
+        Ok(cost)
+    }
+
+    /// Funding rate cap per interval in FUNDING_RATE_PRECISION units.
+    pub fn max_funding_rate(&self) -> i64 {
+        (self.max_funding_rate_bps as u128 * FUNDING_RATE_PRECISION / BPS_PRECISION as u128) as i64
+    }
+
+    /// Current funding rate per interval: the mark premium over index,
+    /// (mark - index) / index, clamped by the market's cap. Longs pay when positive.
+    pub fn funding_rate(&self) -> Result<i64> {
+        let premium = calculate_premium(self.total_long_oi, self.total_short_oi, self.skew_scale)?;
+        let max_rate = self.max_funding_rate();
+        Ok(premium.clamp(-max_rate, max_rate))
+    }
+
+    /// Accrue funding at the current rate for the seconds elapsed since the
+    /// last accrual. Must run before open interest or a position's funding
+    /// snapshot changes so the elapsed period is charged at the rate that
+    /// applied during it. Cumulative rates are in rate-seconds.
+    pub fn accrue_funding(&mut self, now: i64) -> Result<i64> {
+        let funding_rate = self.funding_rate()?;
+        let elapsed = now
+            .checked_sub(self.last_funding_time)
+            .ok_or(PerpsError::MathOverflow)?;
+        if elapsed <= 0 {
+            return Ok(funding_rate);
+        }
+
+        let accrued = (funding_rate as i128)
+            .checked_mul(elapsed as i128)
+            .ok_or(PerpsError::MathOverflow)?;
+        self.cumulative_funding_rate_long = self
+            .cumulative_funding_rate_long
+            .checked_add(accrued)
+            .ok_or(PerpsError::MathOverflow)?;
+        self.cumulative_funding_rate_short = self
+            .cumulative_funding_rate_short
+            .checked_sub(accrued)
+            .ok_or(PerpsError::MathOverflow)?;
+        self.last_funding_time = now;
+
+        Ok(funding_rate)
+    }
+
+    /// Margin ratio a position must hold after margin is taken out of it:
+    /// the ratio of a position opened at max leverage.
+    pub fn initial_margin_bps(&self) -> u64 {
+        BPS_PRECISION / self.max_leverage.max(1)
+    }
+
+    /// Add size and entry notional to the open interest of one side, within
+    /// the side and market caps.
+    pub fn increase_open_interest(
+        &mut self,
+        direction: Direction,
+        size: u64,
+        notional: u64,
+    ) -> Result<()> {
+        match direction {
+            Direction::Long => {
+                self.total_long_size = self
+                    .total_long_size
+                    .checked_add(size)
+                    .ok_or(PerpsError::MathOverflow)?;
+                self.total_long_oi = self
+                    .total_long_oi
+                    .checked_add(notional)
+                    .ok_or(PerpsError::MathOverflow)?;
+                require!(
+                    self.total_long_oi <= self.max_long_oi,
+                    PerpsError::SideOpenInterestCapExceeded
+                );
+            }
+            Direction::Short => {
+                self.total_short_size = self
+                    .total_short_size
+                    .checked_add(size)
+                    .ok_or(PerpsError::MathOverflow)?;
+                self.total_short_oi = self
+                    .total_short_oi
+                    .checked_add(notional)
+                    .ok_or(PerpsError::MathOverflow)?;
+                require!(
+                    self.total_short_oi <= self.max_short_oi,
+                    PerpsError::SideOpenInterestCapExceeded
+                );
+            }
+        }
+        require!(
+            self.total_long_oi
+                .checked_add(self.total_short_oi)
+                .ok_or(PerpsError::MathOverflow)?
+                <= self.max_open_interest,
+            PerpsError::OpenInterestCapExceeded
+        );
+        Ok(())
+    }
+
+    /// Remove size and entry notional from the open interest of one side.
+    pub fn decrease_open_interest(&mut self, direction: Direction, size: u64, notional: u64) {
+        match direction {
+            Direction::Long => {
+                self.total_long_size = self.total_long_size.saturating_sub(size);
+                self.total_long_oi = self.total_long_oi.saturating_sub(notional);
+            }
+            Direction::Short => {
+                self.total_short_size = self.total_short_size.saturating_sub(size);
+                self.total_short_oi = self.total_short_oi.saturating_sub(notional);
+            }
+        }
+    }
+
+    /// Unrealized price PnL of all open positions at `price`, from the
+    /// traders' side: longs gain size * price - entry notional, shorts the reverse.
+    pub fn unrealized_trader_pnl(&self, price: u64) -> Result<i64> {
+        let long_value = calculate_notional(self.total_long_size, price)? as i128;
+        let short_value = calculate_notional(self.total_short_size, price)? as i128;
+        let pnl = (long_value - self.total_long_oi as i128)
+            + (self.total_short_oi as i128 - short_value);
+
+        i64::try_from(pnl).map_err(|_| PerpsError::MathOverflow.into())
+    }
+
+    /// Whether any position is open on this market.
+    pub fn has_open_interest(&self) -> bool {
+        self.total_long_size > 0 || self.total_short_size > 0
+    }
+
+    /// Cumulative funding rate paid by the given side since the market was
+    /// listed, in rate-seconds. Current only after `accrue_funding`.
+    pub fn cumulative_funding_rate(&self, direction: Direction) -> i128 {
+        match direction {
+            Direction::Long => self.cumulative_funding_rate_long,
+            Direction::Short => self.cumulative_funding_rate_short,
+        }
+    }
+}
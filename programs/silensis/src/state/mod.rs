@@ -1,7 +1,15 @@
 pub mod global;
+pub mod insurance;
+pub mod liquidity_pool;
+pub mod market;
+pub mod order;
 pub mod position;
 pub mod vault;
 
 pub use global::*;
+pub use insurance::*;
+pub use liquidity_pool::*;
+pub use market::*;
+pub use order::*;
 pub use position::*;
 pub use vault::*;
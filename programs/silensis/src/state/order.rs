@@ -0,0 +1,66 @@
+use anchor_lang::prelude::*;
+use crate::constants::{BPS_PRECISION, ORDER_SHORT_FILL_BAND_BPS};
+use crate::errors::PerpsError;
+use crate::state::Direction;
+
+/// Resting limit order to open a position, filled by any keeper once the
+/// oracle price crosses `limit_price`.
+#[account]
+#[derive(Default)]
+pub struct Order {
+    pub owner: Pubkey,
+    pub market_index: u16,
+    pub order_id: u64,
+    pub direction: Direction,
+    pub size: u64,        // base asset units (lamport precision)
+    pub limit_price: u64, // 6 decimals; max for longs, min for shorts
+    pub leverage: u64,
+    pub escrow: u64,        // margin and max taker fee at the worst fill plus execution fee, locked in the vault
+    pub execution_fee: u64, // paid to the keeper out of the escrow
+    pub expiry: i64,
+    pub bump: u8,
+}
+
+impl Order {
+    pub const LEN: usize = 8  // discriminator
+        + 32  // owner
+        + 2   // market_index
+        + 8   // order_id
+        + 1   // direction
+        + 8   // size
+        + 8   // limit_price
+        + 8   // leverage
+        + 8   // escrow
+        + 8   // execution_fee
+        + 8   // expiry
+        + 1;  // bump
+
+    /// Worst price an order at `limit_price` may fill at, which its escrow is
+    /// sized for: the limit for a long, and the limit plus
+    /// `ORDER_SHORT_FILL_BAND_BPS` for a short.
+    pub fn worst_fill_price(direction: Direction, limit_price: u64) -> Result<u64> {
+        match direction {
+            Direction::Long => Ok(limit_price),
+            Direction::Short => Ok((limit_price as u128)
+                .checked_mul((BPS_PRECISION + ORDER_SHORT_FILL_BAND_BPS) as u128)
+                .ok_or(PerpsError::MathOverflow)?
+                .checked_div(BPS_PRECISION as u128)
+                .ok_or(PerpsError::MathOverflow)?
+                .try_into()
+                .map_err(|_| PerpsError::MathOverflow)?),
+        }
+    }
+
+    /// Whether `price` is at or better than the limit and within what the
+    /// escrow covers: at or below the limit for longs, and for shorts at or
+    /// above it up to the worst fill price.
+    pub fn is_triggered(&self, price: u64) -> Result<bool> {
+        Ok(match self.direction {
+            Direction::Long => price <= self.limit_price,
+            Direction::Short => {
+                price >= self.limit_price
+                    && price <= Self::worst_fill_price(self.direction, self.limit_price)?
+            }
+        })
+    }
+}
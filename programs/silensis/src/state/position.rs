@@ -1,4 +1,5 @@
 use anchor_lang::prelude::*;
+use crate::math::calculate_trailing_stop_price;
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default)]
 pub enum Direction {
@@ -7,10 +8,30 @@ pub enum Direction {
     Short,
 }
 
+impl Direction {
+    /// Side of the trade that closes a position in this direction.
+    pub fn opposite(self) -> Self {
+        match self {
+            Direction::Long => Direction::Short,
+            Direction::Short => Direction::Long,
+        }
+    }
+
+    /// Whether a trade on this side fills within `acceptable_price`: at or
+    /// below it when buying, at or above it when selling.
+    pub fn is_price_acceptable(self, fill_price: u64, acceptable_price: u64) -> bool {
+        match self {
+            Direction::Long => fill_price <= acceptable_price,
+            Direction::Short => fill_price >= acceptable_price,
+        }
+    }
+}
+
 #[account]
 #[derive(Default)]
 pub struct Position {
     pub owner: Pubkey,
+    pub market_index: u16,
     pub position_id: u64,
     pub direction: Direction,
     pub size: u64,       // base asset units (lamport precision)
@@ -18,7 +39,11 @@ pub struct Position {
     pub leverage: u64,
     pub margin: u64,     // USDC amount
     pub last_funding_time: i64,
-    pub cumulative_funding: i64,
+    pub cumulative_funding: i128, // market cumulative funding rate at open
+    pub stop_loss_price: u64,   // 0 when unset
+    pub take_profit_price: u64, // 0 when unset
+    pub trailing_stop_bps: u64, // 0 when unset
+    pub trailing_price: u64,    // best oracle price seen while trailing
     pub is_open: bool,
     pub bump: u8,
 }
@@ -26,6 +51,7 @@ pub struct Position {
 impl Position {
     pub const LEN: usize = 8  // discriminator
         + 32  // owner
+        + 2   // market_index
         + 8   // position_id
         + 1   // direction
         + 8   // size
@@ -33,7 +59,55 @@ impl Position {
         + 8   // leverage
         + 8   // margin
         + 8   // last_funding_time
-        + 8   // cumulative_funding
+        + 16  // cumulative_funding
+        + 8   // stop_loss_price
+        + 8   // take_profit_price
+        + 8   // trailing_stop_bps
+        + 8   // trailing_price
         + 1   // is_open
         + 1;  // bump
+
+    /// Ratchet the trailing stop's reference to `price` if it is a new high
+    /// for a long or a new low for a short. Returns whether it moved.
+    pub fn update_trailing_price(&mut self, price: u64) -> bool {
+        if self.trailing_stop_bps == 0 {
+            return false;
+        }
+
+        let improved = match self.direction {
+            Direction::Long => price > self.trailing_price,
+            Direction::Short => price < self.trailing_price,
+        };
+        if improved {
+            self.trailing_price = price;
+        }
+
+        improved
+    }
+
+    /// Whether `price` has crossed the stop-loss, take-profit or trailing stop.
+    pub fn is_trigger_crossed(&self, price: u64) -> Result<bool> {
+        let sl = self.stop_loss_price;
+        let tp = self.take_profit_price;
+        let (stop_loss_hit, take_profit_hit) = match self.direction {
+            Direction::Long => (sl > 0 && price <= sl, tp > 0 && price >= tp),
+            Direction::Short => (sl > 0 && price >= sl, tp > 0 && price <= tp),
+        };
+
+        let trailing_stop_hit = if self.trailing_stop_bps > 0 {
+            let stop = calculate_trailing_stop_price(
+                self.direction,
+                self.trailing_price,
+                self.trailing_stop_bps,
+            )?;
+            match self.direction {
+                Direction::Long => price <= stop,
+                Direction::Short => price >= stop,
+            }
+        } else {
+            false
+        };
+
+        Ok(stop_loss_hit || take_profit_hit || trailing_stop_hit)
+    }
 }
@@ -1,4 +1,14 @@
 use anchor_lang::prelude::*;
+use crate::errors::PerpsError;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MarginMode {
+    /// Each position is backed only by its own margin
+    #[default]
+    Isolated,
+    /// The whole vault backs every position; liquidated on account health
+    Cross,
+}
 
 #[account]
 #[derive(Default)]
@@ -6,6 +16,9 @@ pub struct UserVault {
     pub owner: Pubkey,
     pub deposited_amount: u64,
     pub locked_margin: u64,
+    pub margin_mode: MarginMode,
+    pub open_positions: u32,
+    pub open_notional: u64, // entry notional of open positions across markets
     pub bump: u8,
 }
 
@@ -14,5 +27,97 @@ impl UserVault {
         + 32  // owner
         + 8   // deposited_amount
         + 8   // locked_margin
+        + 1   // margin_mode
+        + 4   // open_positions
+        + 8   // open_notional
         + 1;  // bump
 }
+
+impl UserVault {
+    /// Balance not locked as margin by open positions. A cross-margin vault
+    /// whose unrealized profit backs its positions can lock more than it holds.
+    pub fn free_balance(&self) -> Result<u64> {
+        Ok(self.deposited_amount.saturating_sub(self.locked_margin))
+    }
+
+    /// Add entry notional of a new or grown position, within the per-user cap.
+    pub fn increase_open_notional(&mut self, notional: u64, max_user_notional: u64) -> Result<()> {
+        self.open_notional = self
+            .open_notional
+            .checked_add(notional)
+            .ok_or(PerpsError::MathOverflow)?;
+        require!(
+            self.open_notional <= max_user_notional,
+            PerpsError::UserNotionalCapExceeded
+        );
+        Ok(())
+    }
+
+    /// Remove entry notional of a closed or reduced position.
+    pub fn decrease_open_notional(&mut self, notional: u64) {
+        self.open_notional = self.open_notional.saturating_sub(notional);
+    }
+
+    /// Unlock `margin` and settle it against `pnl`.
+    /// Returns the amount credited back (margin + pnl, clamped to 0) and the
+    /// shortfall: any loss beyond margin that the position cannot cover.
+    /// In cross-margin mode a loss beyond margin is first taken from the rest
+    /// of the deposit.
+    pub fn settle_margin(&mut self, margin: u64, pnl: i64) -> Result<(u64, u64)> {
+        let (settlement, shortfall) = if pnl >= 0 {
+            let settlement = margin
+                .checked_add(pnl as u64)
+                .ok_or(PerpsError::MathOverflow)?;
+            (settlement, 0)
+        } else {
+            let loss = pnl.unsigned_abs();
+            (margin.saturating_sub(loss), loss.saturating_sub(margin))
+        };
+
+        self.locked_margin = self
+            .locked_margin
+            .checked_sub(margin)
+            .ok_or(PerpsError::MathOverflow)?;
+
+        let shortfall = if self.margin_mode == MarginMode::Cross {
+            // The deposit may hold less than the margin it locks, so settle
+            // pnl against all of it rather than unlocking margin first
+            let balance = (self.deposited_amount as i128)
+                .checked_add(pnl as i128)
+                .ok_or(PerpsError::MathOverflow)?;
+            self.deposited_amount = balance.max(0) as u64;
+            (-balance).max(0) as u64
+        } else {
+            self.deposited_amount = self
+                .deposited_amount
+                .checked_sub(margin)
+                .ok_or(PerpsError::MathOverflow)?
+                .checked_add(settlement)
+                .ok_or(PerpsError::MathOverflow)?;
+            shortfall
+        };
+
+        Ok((settlement, shortfall))
+    }
+
+    /// Charge a fee from free balance, capped at what is available.
+    /// Returns the amount actually charged.
+    pub fn charge_fee(&mut self, fee: u64) -> Result<u64> {
+        let fee = fee.min(self.free_balance()?);
+        self.deposited_amount -= fee;
+
+        Ok(fee)
+    }
+
+    /// Credit a fee earned by `owner` to their vault, which may have just
+    /// been created by `init_if_needed`.
+    pub fn credit(&mut self, owner: Pubkey, bump: u8, amount: u64) -> Result<()> {
+        self.owner = owner;
+        self.bump = bump;
+        self.deposited_amount = self
+            .deposited_amount
+            .checked_add(amount)
+            .ok_or(PerpsError::MathOverflow)?;
+        Ok(())
+    }
+}